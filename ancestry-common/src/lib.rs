@@ -1,2 +1,26 @@
 pub type SignedInteger = i32;
 pub type LargeSignedInteger = i64;
+
+/// Node metadata that has already been encoded,
+/// so that metadata of any type can be stored together.
+pub struct EncodedNodeMetadata(Vec<u8>);
+
+impl EncodedNodeMetadata {
+    pub fn new<M: tskit::metadata::NodeMetadata>(
+        metadata: &M,
+    ) -> Result<Self, tskit::metadata::MetadataError> {
+        metadata.encode().map(Self)
+    }
+}
+
+impl tskit::metadata::MetadataRoundtrip for EncodedNodeMetadata {
+    fn encode(&self) -> Result<Vec<u8>, tskit::metadata::MetadataError> {
+        Ok(self.0.clone())
+    }
+
+    fn decode(md: &[u8]) -> Result<Self, tskit::metadata::MetadataError> {
+        Ok(Self(md.to_vec()))
+    }
+}
+
+impl tskit::metadata::NodeMetadata for EncodedNodeMetadata {}
@@ -1,6 +1,4 @@
-use crate::{
-    node::Node, AncestryIntersection, HalfOpenInterval, LargeSignedInteger, SignedInteger,
-};
+use crate::{node::Node, AncestryIntersection, LargeSignedInteger, SignedInteger};
 use std::cell::RefCell;
 use std::rc::Rc;
 
@@ -145,13 +143,15 @@ mod overlapper_tests {
 
     impl FailingExamples {
         fn new() -> Self {
-            let data = vec![vec![
-                (0_i64, 69_i64),
-                (0, 100),
-                (60, 69),
-                (69, 100),
-                (69, 100),
-            ]];
+            let data = vec![
+                vec![(0_i64, 69_i64), (0, 100), (60, 69), (69, 100), (69, 100)],
+                // gap between segments
+                vec![(0, 5), (10, 15)],
+                // nested segment
+                vec![(0, 10), (2, 3)],
+                // identical segments
+                vec![(3, 7), (3, 7), (3, 7)],
+            ];
             Self { data }
         }
 
@@ -183,4 +183,100 @@ mod overlapper_tests {
             for (_i, (_left, _right, _overlaps)) in overlapper.enumerate() {}
         }
     }
+
+    // Check the overlapper output against a brute-force
+    // calculation over every position of the input.
+    // The child of each input is labelled by its index
+    // in the input so that duplicate segments are distinguished.
+    fn validate_overlaps(input: &[(i64, i64)]) {
+        let intersections = input
+            .iter()
+            .enumerate()
+            .map(|(i, p)| AncestryIntersection::new(p.0, p.1, Node::new_alive(i as i32, 1)))
+            .collect::<Vec<AncestryIntersection>>();
+        let covering = |left: i64, right: i64| {
+            let mut rv = input
+                .iter()
+                .enumerate()
+                .filter(|(_, p)| p.0 <= left && p.1 >= right)
+                .map(|(i, _)| i as i32)
+                .collect::<Vec<i32>>();
+            rv.sort_unstable();
+            rv
+        };
+
+        let mut last_right = i64::MIN;
+        let mut output_length = 0;
+        for (left, right, overlaps) in AncestryOverlapper::new(intersections) {
+            assert!(left < right, "{:?}", input);
+            assert!(left >= last_right, "{:?}", input);
+            // Any gap between outputs must be a gap in the input
+            if last_right != i64::MIN {
+                for p in last_right..left {
+                    assert!(covering(p, p + 1).is_empty(), "{:?}", input);
+                }
+            }
+            last_right = right;
+            output_length += right - left;
+
+            let mut children = overlaps
+                .borrow()
+                .iter()
+                .map(|o| o.mapped_id())
+                .collect::<Vec<i32>>();
+            children.sort_unstable();
+            assert!(!children.is_empty(), "{:?}", input);
+            assert_eq!(children, covering(left, right), "{:?}", input);
+        }
+
+        let max_right = input.iter().map(|p| p.1).max().unwrap();
+        let union_length = (0..max_right)
+            .filter(|&p| !covering(p, p + 1).is_empty())
+            .count() as i64;
+        assert_eq!(output_length, union_length, "{:?}", input);
+    }
+
+    #[test]
+    fn test_overlap_invariants_of_known_examples() {
+        for a in FailingExamples::new().data {
+            validate_overlaps(&a);
+        }
+    }
+
+    #[test]
+    fn test_overlap_invariants_of_regression_inputs() {
+        for input in [
+            // abutting segments, with and without a gap after
+            vec![(0, 5), (5, 10)],
+            vec![(0, 5), (5, 10), (12, 14)],
+            // every segment ends at the last position
+            vec![(0, 20), (5, 20), (19, 20)],
+            // a long segment spanning several gaps between short ones
+            vec![(1, 2), (0, 20), (4, 6), (10, 11)],
+            // single-position segments
+            vec![(3, 4), (3, 4), (4, 5)],
+        ] {
+            validate_overlaps(&input);
+        }
+    }
+
+    #[test]
+    fn test_overlap_invariants_of_random_segments() {
+        use rand::Rng;
+        use rand::SeedableRng;
+
+        for seed in [0_u64, 42, 101, 31415, 999999, 8675309, 20220211] {
+            let mut rng = rand_pcg::Pcg64::seed_from_u64(seed);
+            for _ in 0..500 {
+                let n = rng.gen_range(1..12);
+                let input = (0..n)
+                    .map(|_| {
+                        let left = rng.gen_range(0..20_i64);
+                        (left, rng.gen_range(left + 1..=20))
+                    })
+                    .collect::<Vec<(i64, i64)>>();
+                validate_overlaps(&input);
+            }
+        }
+    }
 }
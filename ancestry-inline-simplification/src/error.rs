@@ -1,4 +1,5 @@
 use crate::LargeSignedInteger;
+use crate::SignedInteger;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -19,10 +20,25 @@ pub enum InlineAncestryError {
         parent: LargeSignedInteger,
         child: LargeSignedInteger,
     },
+    #[error("birth time {birth_time:?} is not after parent birth time {parent_birth_time:?}")]
+    NonMonotonicBirth {
+        birth_time: LargeSignedInteger,
+        parent_birth_time: LargeSignedInteger,
+    },
+    #[error("segments do not tile genome of length {l:?}")]
+    GenomeNotTiled { l: LargeSignedInteger },
+    #[error("invalid node index: {index:?}")]
+    InvalidNodeIndex { index: usize },
+    #[error("number of deaths ({deaths:?}) does not equal number of births ({births:?})")]
+    BirthDeathMismatch { deaths: usize, births: usize },
+    #[error("need metadata for each of {popsize:?} founders, got {len:?}")]
+    FounderMetadataLength { popsize: usize, len: usize },
     #[error("unexpected dead node")]
     DeadNode,
     #[error("unreachable child in graph")]
     UnreachableChild,
+    #[error("ancestry of alive node {index:?} is not a single segment covering the genome")]
+    InvalidAliveAncestry { index: SignedInteger },
     #[error("{0:?}")]
     TskitError(tskit::TskitError),
 }
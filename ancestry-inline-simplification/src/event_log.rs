@@ -0,0 +1,39 @@
+use crate::LargeSignedInteger;
+
+/// A change to a [`Population`](crate::Population).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// The alive node at index `who` dies.
+    Death { who: usize },
+    /// A birth inheriting `(parent index, left, right)` segments.
+    Birth {
+        birth_time: LargeSignedInteger,
+        segments: Vec<(usize, LargeSignedInteger, LargeSignedInteger)>,
+    },
+    /// Deaths are replaced by births and ancestry is simplified.
+    Simplify { time: LargeSignedInteger },
+}
+
+/// The events applied to a [`Population`](crate::Population),
+/// in order.
+///
+/// See [`Population::log_events`](crate::Population::log_events)
+/// and [`Population::replay`](crate::Population::replay).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct EventLog {
+    events: Vec<Event>,
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub(crate) fn push(&mut self, event: Event) {
+        self.events.push(event);
+    }
+}
@@ -0,0 +1,113 @@
+use crate::node::Node;
+use crate::AncestrySegment;
+use crate::HalfOpenInterval;
+use crate::InlineAncestryError;
+use crate::LargeSignedInteger;
+
+/// Returns the segment of `segments` containing `position`.
+///
+/// `segments` must be sorted and non-overlapping.
+pub(crate) fn stab<T: HalfOpenInterval>(
+    segments: &[T],
+    position: LargeSignedInteger,
+) -> Option<&T> {
+    let i = segments.partition_point(|s| s.right() <= position);
+    segments.get(i).filter(|s| s.left() <= position)
+}
+
+/// Position queries over a copy of a node's ancestry.
+///
+/// Ancestry segments are sorted and do not overlap,
+/// so queries are binary searches.
+#[derive(Clone, Debug)]
+pub struct IntervalIndex {
+    segments: Vec<AncestrySegment>,
+}
+
+impl IntervalIndex {
+    /// Fails if `segments` are not sorted or overlap.
+    pub fn new(segments: Vec<AncestrySegment>) -> Result<Self, InlineAncestryError> {
+        crate::util::non_overlapping_segments(&segments)?;
+        Ok(Self { segments })
+    }
+
+    pub fn from_node(node: &Node) -> Result<Self, InlineAncestryError> {
+        Self::new(node.borrow().ancestry.clone())
+    }
+
+    /// The segment containing `position`, if any.
+    pub fn find(&self, position: LargeSignedInteger) -> Option<&AncestrySegment> {
+        stab(&self.segments, position)
+    }
+
+    /// The segments overlapping `[left, right)`, in order.
+    pub fn overlapping(
+        &self,
+        left: LargeSignedInteger,
+        right: LargeSignedInteger,
+    ) -> &[AncestrySegment] {
+        let start = self.segments.partition_point(|s| s.right() <= left);
+        let stop = self.segments.partition_point(|s| s.left() < right);
+        &self.segments[start..stop.max(start)]
+    }
+
+    pub fn segments(&self) -> &[AncestrySegment] {
+        &self.segments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interval_index_queries() {
+        let node = Node::new_alive(0, 0);
+        let children = (1..4).map(|i| Node::new_alive(i, 1)).collect::<Vec<_>>();
+        node.borrow_mut().ancestry = vec![
+            AncestrySegment::new(0, 10, children[0].clone()),
+            AncestrySegment::new(10, 20, children[1].clone()),
+            AncestrySegment::new(30, 40, children[2].clone()),
+        ];
+        let index = IntervalIndex::from_node(&node).unwrap();
+
+        for (position, expected) in [
+            (0, Some(&children[0])),
+            (9, Some(&children[0])),
+            (10, Some(&children[1])),
+            (25, None),
+            (30, Some(&children[2])),
+            (39, Some(&children[2])),
+            (40, None),
+            (-1, None),
+        ] {
+            assert_eq!(
+                index.find(position).map(|s| &s.child),
+                expected,
+                "{}",
+                position
+            );
+            assert_eq!(
+                crate::util::ancestry_mapped_at(&node, position).as_ref(),
+                expected,
+                "{}",
+                position
+            );
+        }
+
+        assert_eq!(index.overlapping(5, 15), &index.segments()[0..2]);
+        assert_eq!(index.overlapping(10, 35), &index.segments()[1..3]);
+        assert!(index.overlapping(20, 30).is_empty());
+        assert!(index.overlapping(40, 50).is_empty());
+    }
+
+    #[test]
+    fn test_interval_index_rejects_overlaps() {
+        let child = Node::new_alive(1, 1);
+        assert!(IntervalIndex::new(vec![
+            AncestrySegment::new(0, 10, child.clone()),
+            AncestrySegment::new(5, 15, child),
+        ])
+        .is_err());
+    }
+}
@@ -2,10 +2,14 @@ pub use ancestry_common::{LargeSignedInteger, SignedInteger};
 
 mod ancestry_overlapper;
 mod error;
+mod event_log;
 mod flags;
+mod interval_index;
+mod mutation;
 mod node_heap;
 mod propagate_ancestry_changes;
 mod segments;
+mod stats;
 mod update_ancestry;
 mod util;
 
@@ -19,8 +23,13 @@ pub mod population;
 // NOTE: this API is TBD, and may later
 // be exported via a pub mod.
 pub use error::InlineAncestryError;
+pub use event_log::{Event, EventLog};
 pub use flags::NodeFlags;
+pub use interval_index::IntervalIndex;
+pub use mutation::Mutation;
 pub use node::Node;
 pub use node::NodeData;
 pub use node_heap::NodeHeap;
 pub use population::Population;
+pub use population::{TableConversionOptions, TimeDirection};
+pub use segments::{AncestryIntersection, AncestrySegment};
@@ -0,0 +1,20 @@
+use crate::node::Node;
+use crate::LargeSignedInteger;
+
+/// A mutation carried by a [Node](Node) at a given position.
+///
+/// The node is held by pointer, so a mutation does not
+/// keep track of whether or not its node is still part
+/// of the ancestry.  That decision is made when exporting
+/// the ancestry, e.g., to tskit.
+#[derive(Clone, Debug)]
+pub struct Mutation {
+    pub node: Node,
+    pub position: LargeSignedInteger,
+}
+
+impl Mutation {
+    pub fn new(node: Node, position: LargeSignedInteger) -> Self {
+        Self { node, position }
+    }
+}
@@ -1,7 +1,6 @@
+use crate::propagate_ancestry_changes::PropagationStats;
 use crate::InlineAncestryError;
-use crate::{
-    AncestrySegment, HalfOpenInterval, LargeSignedInteger, NodeFlags, Segment, SignedInteger,
-};
+use crate::{AncestrySegment, LargeSignedInteger, NodeFlags, Segment, SignedInteger};
 use std::fmt::Debug;
 use std::hash::{Hash, Hasher};
 use std::rc::Rc;
@@ -23,6 +22,12 @@ use hashbrown::{HashMap, HashSet};
 pub struct Node(Rc<RefCell<NodeData>>);
 
 pub type ChildMap = HashMap<Node, Vec<Segment>>;
+/// Parents are strong references: the alive nodes own
+/// their ancestors through these sets.
+/// The reference cycles with [`ChildMap`] and the ancestry
+/// segments are broken during simplification, which removes
+/// a node from its children's parent sets once it no longer
+/// overlaps them, so unreachable nodes are freed.
 pub type ParentSet = HashSet<Node>;
 
 #[derive(Clone)] // NOTE: this does not have to be Clone b/c we work via pointers
@@ -127,8 +132,11 @@ impl Node {
 
     #[inline(never)]
     // TODO: instead of pup(crate), this should perhaps be standalone?
-    pub(crate) fn update_ancestry(&mut self) -> Result<bool, InlineAncestryError> {
-        let rv = crate::update_ancestry::update_ancestry(self);
+    pub(crate) fn update_ancestry(
+        &mut self,
+        stats: &mut PropagationStats,
+    ) -> Result<bool, InlineAncestryError> {
+        let rv = crate::update_ancestry::update_ancestry(self, stats);
         Ok(rv)
     }
 
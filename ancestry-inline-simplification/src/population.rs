@@ -1,12 +1,45 @@
+use crate::event_log::{Event, EventLog};
+use crate::mutation::Mutation;
 use crate::node::Node;
 use crate::node_heap::NodeHeap;
+use crate::propagate_ancestry_changes::PropagationStats;
+use crate::segments::Segment;
 use crate::InlineAncestryError;
 use crate::LargeSignedInteger;
 use crate::SignedInteger;
-use hashbrown::HashSet;
+use ancestry_common::EncodedNodeMetadata;
+use hashbrown::{HashMap, HashSet};
 use neutral_evolution::EvolveAncestry;
 use tskit::prelude::*;
 
+/// How [`Population::to_tables_with`] converts birth times
+/// into tskit node times.
+///
+/// Either way, parents are older than their children,
+/// as tskit requires.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimeDirection {
+    /// Time is measured backwards from the youngest node,
+    /// which has time zero.
+    #[default]
+    Backward,
+    /// Time is measured forwards from birth time zero:
+    /// the node time is the negated birth time.
+    Forward,
+}
+
+/// Options for [`Population::to_tables_with`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TableConversionOptions {
+    pub time_direction: TimeDirection,
+}
+
+/// A population of constant size.
+///
+/// Each death is replaced by exactly one birth when simplifying.
+/// Any fraction of the population may die in a single time step,
+/// including all of it (complete turnover, as in a Wright-Fisher
+/// model).
 pub struct Population {
     next_node_id: SignedInteger,
     genome_length: LargeSignedInteger,
@@ -14,23 +47,92 @@ pub struct Population {
     births: Vec<Node>,
     next_replacement: usize,
     node_heap: NodeHeap,
+    mutations: Vec<Mutation>,
+    last_propagation_stats: PropagationStats,
+    track_ancestor_counts: bool,
+    ancestor_count_history: Vec<(LargeSignedInteger, usize)>,
+    track_freed_counts: bool,
+    last_freed_count: Option<usize>,
+    track_edge_counts: bool,
+    edge_count_history: Vec<(LargeSignedInteger, usize)>,
+    batch_size: Option<usize>,
+    event_log: Option<EventLog>,
+    full_history: Option<FullHistory>,
+    defer_propagation: bool,
+    verify_after_simplify: bool,
+    // cfg!(debug_assertions), kept in a field
+    // so that tests can behave as release builds.
+    debug_validation: bool,
+    deferred_deaths: Vec<Node>,
+    deferred_births: Vec<Node>,
+    // Keyed on node index.
+    founder_metadata: HashMap<SignedInteger, EncodedNodeMetadata>,
     pub nodes: Vec<Node>,
 }
 
+// The alive nodes when recording began, and the
+// (time, replaced index, birth) of each later replacement,
+// grouped by simplification.
+struct FullHistory {
+    initial_nodes: Vec<Node>,
+    generations: Vec<(LargeSignedInteger, Vec<(usize, Node)>)>,
+}
+
 impl Population {
     pub fn new(
         popsize: SignedInteger,
         genome_length: LargeSignedInteger,
     ) -> Result<Self, InlineAncestryError> {
-        if genome_length > 0 {
-            let next_node_id = popsize;
+        let mut nodes = vec![];
 
-            let mut nodes = vec![];
+        for i in 0..popsize {
+            let node = Node::new_alive_with_ancestry_mapping_to_self(i, 0, genome_length);
+            nodes.push(node);
+        }
 
-            for i in 0..next_node_id {
-                let node = Node::new_alive_with_ancestry_mapping_to_self(i, 0, genome_length);
-                nodes.push(node);
-            }
+        Self::with_initial_nodes(nodes, genome_length)
+    }
+
+    /// Create a population whose founders carry `metadata`,
+    /// which is written to the tskit node table by
+    /// [`Population::to_tables`].
+    ///
+    /// Fails if the length of `metadata` is not `popsize`.
+    pub fn new_with_founder_metadata<M: tskit::metadata::NodeMetadata>(
+        popsize: SignedInteger,
+        genome_length: LargeSignedInteger,
+        metadata: Vec<M>,
+    ) -> Result<Self, InlineAncestryError> {
+        if metadata.len() != popsize as usize {
+            return Err(InlineAncestryError::FounderMetadataLength {
+                popsize: popsize as usize,
+                len: metadata.len(),
+            });
+        }
+        let mut pop = Self::new(popsize, genome_length)?;
+        for (i, m) in metadata.iter().enumerate() {
+            let encoded = EncodedNodeMetadata::new(m).map_err(|value| {
+                InlineAncestryError::TskitError(tskit::TskitError::MetadataError { value })
+            })?;
+            pop.founder_metadata.insert(i as SignedInteger, encoded);
+        }
+        Ok(pop)
+    }
+
+    /// Create a population whose alive nodes are `nodes`.
+    ///
+    /// New node ids start after the largest id in `nodes`.
+    /// This is the inverse of [`Population::into_parts`].
+    pub fn with_initial_nodes(
+        nodes: Vec<Node>,
+        genome_length: LargeSignedInteger,
+    ) -> Result<Self, InlineAncestryError> {
+        if genome_length > 0 {
+            let next_node_id = nodes
+                .iter()
+                .map(|n| n.borrow().index + 1)
+                .max()
+                .unwrap_or(0);
 
             Ok(Self {
                 next_node_id,
@@ -39,6 +141,23 @@ impl Population {
                 births: vec![],
                 next_replacement: 0,
                 node_heap: NodeHeap::default(),
+                mutations: vec![],
+                last_propagation_stats: PropagationStats::default(),
+                track_ancestor_counts: false,
+                ancestor_count_history: vec![],
+                track_freed_counts: false,
+                last_freed_count: None,
+                track_edge_counts: false,
+                edge_count_history: vec![],
+                batch_size: None,
+                event_log: None,
+                full_history: None,
+                defer_propagation: false,
+                verify_after_simplify: false,
+                debug_validation: cfg!(debug_assertions),
+                deferred_deaths: vec![],
+                deferred_births: vec![],
+                founder_metadata: HashMap::default(),
                 nodes,
             })
         } else {
@@ -46,6 +165,12 @@ impl Population {
         }
     }
 
+    /// Deconstruct into the alive nodes and the genome length,
+    /// discarding all other bookkeeping.
+    pub fn into_parts(self) -> (Vec<Node>, LargeSignedInteger) {
+        (self.nodes, self.genome_length)
+    }
+
     pub fn birth(&mut self, birth_time: LargeSignedInteger) -> Node {
         assert!(birth_time >= 0);
         let index = self.next_node_id;
@@ -69,6 +194,12 @@ impl Population {
         self.nodes.is_empty()
     }
 
+    /// The stored index of each alive node, in the
+    /// same order as [`Population::nodes`].
+    pub fn current_sample_ids(&self) -> Vec<SignedInteger> {
+        self.nodes.iter().map(|n| n.borrow().index).collect()
+    }
+
     pub fn all_reachable_nodes(&self) -> HashSet<Node> {
         crate::util::all_reachable_nodes(&self.nodes)
     }
@@ -77,9 +208,770 @@ impl Population {
         self.all_reachable_nodes().len()
     }
 
+    /// All nodes reachable from the alive nodes, sorted by
+    /// birth time and then by index.
+    pub fn reachable_nodes_sorted(&self) -> Vec<Node> {
+        let mut nodes = self.all_reachable_nodes().into_iter().collect::<Vec<_>>();
+        nodes.sort_by_key(|n| {
+            let b = n.borrow();
+            (b.birth_time, b.index)
+        });
+        nodes
+    }
+
+    /// Call `f(parent index, child index, left, right)` for each edge
+    /// among the nodes reachable from the alive nodes.
+    ///
+    /// Parents are visited in the order given by
+    /// [`Population::reachable_nodes_sorted`].
+    pub fn for_each_edge<F>(&self, mut f: F)
+    where
+        F: FnMut(SignedInteger, SignedInteger, LargeSignedInteger, LargeSignedInteger),
+    {
+        let reachable = self.reachable_nodes_sorted();
+        let _ = Self::try_for_each_edge_among::<(), _>(&reachable, |parent, child, segment| {
+            f(parent, child, segment.left, segment.right);
+            Ok(())
+        });
+    }
+
+    /// The alive nodes descending from `node`, as
+    /// `(index into the alive nodes, left, right)`.
+    ///
+    /// The intervals of each alive node are merged
+    /// and the output is sorted.
+    /// `node` itself is not included.
+    pub fn descendant_samples(
+        &self,
+        node: &Node,
+    ) -> Vec<(usize, LargeSignedInteger, LargeSignedInteger)> {
+        let alive = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (n.clone(), i))
+            .collect::<HashMap<Node, usize>>();
+
+        let mut intervals = vec![];
+        let mut stack = vec![(node.clone(), 0, self.genome_length)];
+        while let Some((parent, left, right)) = stack.pop() {
+            for (child, segments) in parent.borrow().children.iter() {
+                for segment in segments {
+                    let l = segment.left.max(left);
+                    let r = segment.right.min(right);
+                    if l < r {
+                        if let Some(&i) = alive.get(child) {
+                            intervals.push((i, l, r));
+                        }
+                        stack.push((child.clone(), l, r));
+                    }
+                }
+            }
+        }
+
+        intervals.sort_unstable();
+        let mut rv: Vec<(usize, LargeSignedInteger, LargeSignedInteger)> = vec![];
+        for (i, l, r) in intervals {
+            match rv.last_mut() {
+                Some(last) if last.0 == i && l <= last.2 => last.2 = last.2.max(r),
+                _ => rv.push((i, l, r)),
+            }
+        }
+        rv
+    }
+
+    /// The number of edges among the nodes reachable
+    /// from the alive nodes.
+    pub fn num_edges(&self) -> usize {
+        let mut rv = 0;
+        self.for_each_edge(|_, _, _, _| rv += 1);
+        rv
+    }
+
+    fn try_for_each_edge_among<E, F>(nodes: &[Node], mut f: F) -> Result<(), E>
+    where
+        F: FnMut(SignedInteger, SignedInteger, &Segment) -> Result<(), E>,
+    {
+        for node in nodes {
+            let borrowed = node.borrow();
+            for (child, segments) in borrowed.children.iter() {
+                let child = child.borrow().index;
+                for segment in segments {
+                    f(borrowed.index, child, segment)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Convert the current state into a [`tskit::TableCollection`].
+    ///
+    /// The output is reproducible: converting the same
+    /// population twice gives identical tables.
+    pub fn to_tables(&self) -> Result<tskit::TableCollection, InlineAncestryError> {
+        self.to_tables_with(TableConversionOptions::default())
+    }
+
+    /// Convert the current state into a [`tskit::TableCollection`]
+    /// using non-default options.
+    pub fn to_tables_with(
+        &self,
+        options: TableConversionOptions,
+    ) -> Result<tskit::TableCollection, InlineAncestryError> {
+        self.debug_assert_samples_cover_genome();
+
+        let mut tables = match tskit::TableCollection::new(self.genome_length() as f64) {
+            Ok(tables) => tables,
+            Err(e) => return Err(crate::InlineAncestryError::TskitError(e)),
+        };
+
+        // Keyed on node index
+        let mut node_map = std::collections::BTreeMap::<SignedInteger, _>::default();
+        let reachable = self.reachable_nodes_sorted();
+        let max_time = reachable
+            .iter()
+            .map(|i| i.borrow().birth_time)
+            .max()
+            .unwrap_or(0);
+        let node_time = |birth_time: LargeSignedInteger| match options.time_direction {
+            TimeDirection::Backward => (max_time - birth_time) as f64,
+            TimeDirection::Forward => -birth_time as f64,
+        };
+
+        // Adding nodes in sorted order makes the output
+        // node ids independent of hashing order,
+        // so identical populations give identical tables.
+        for i in reachable.iter() {
+            let time = node_time(i.borrow().birth_time);
+            let node_id = match self.founder_metadata.get(&i.borrow().index) {
+                Some(md) => tables.add_node_with_metadata(0, time, -1, -1, md),
+                None => tables.add_node(0, time, -1, -1),
+            };
+            let node_id = match node_id {
+                Ok(node_id) => node_id,
+                Err(e) => return Err(crate::InlineAncestryError::TskitError(e)),
+            };
+            node_map.insert(i.borrow().index, node_id);
+        }
+
+        Self::try_for_each_edge_among(&reachable, |parent, child, segment| {
+            match tables.add_edge(
+                segment.left as f64,
+                segment.right as f64,
+                node_map[&parent],
+                node_map[&child],
+            ) {
+                Ok(_) => Ok(()),
+                Err(e) => Err(crate::InlineAncestryError::TskitError(e)),
+            }
+        })?;
+
+        for i in self.nodes.iter() {
+            let node = node_map.get(&i.borrow().index).unwrap();
+            tables.nodes().flags_array_mut()[usize::from(*node)] = tskit::NodeFlags::IS_SAMPLE;
+        }
+
+        // Mutations are placed on the node that their node's
+        // ancestry maps to at their position, following the
+        // mapping through nodes removed from the graph.
+        // They are dropped if no sample ancestry covers
+        // their position.
+        // Keying on position gives us sorted, unique sites.
+        let mut sites = std::collections::BTreeMap::<LargeSignedInteger, Vec<Node>>::default();
+        for m in self.mutations.iter() {
+            if let Some(mapped) = crate::util::mutation_mapped_node(&m.node, m.position) {
+                if node_map.contains_key(&mapped.borrow().index) {
+                    sites.entry(m.position).or_default().push(mapped);
+                }
+            }
+        }
+
+        for (position, nodes) in sites.iter() {
+            let site = match tables.add_site(*position as f64, None) {
+                Ok(site) => site,
+                Err(e) => return Err(crate::InlineAncestryError::TskitError(e)),
+            };
+            for node in nodes {
+                let time = node_time(node.borrow().birth_time);
+                match tables.add_mutation(
+                    site,
+                    node_map[&node.borrow().index],
+                    tskit::MutationId::NULL,
+                    time,
+                    None,
+                ) {
+                    Ok(_) => (),
+                    Err(e) => return Err(crate::InlineAncestryError::TskitError(e)),
+                }
+            }
+        }
+
+        match tables.full_sort(tskit::TableSortOptions::default()) {
+            Ok(_) => (),
+            Err(e) => return Err(crate::InlineAncestryError::TskitError(e)),
+        }
+
+        match tables.build_index() {
+            Ok(_) => (),
+            Err(e) => return Err(crate::InlineAncestryError::TskitError(e)),
+        }
+        Ok(tables)
+    }
+
     pub fn validate_graph(&self) -> Result<(), InlineAncestryError> {
         crate::util::validate_graph(&self.nodes, self.genome_length)
     }
+
+    /// Like [`Population::validate_graph`], but only checks the
+    /// part of the graph reachable from the alive nodes at `samples`.
+    pub fn validate_sample_ancestry(&self, samples: &[usize]) -> Result<(), InlineAncestryError> {
+        let mut nodes = vec![];
+        for &index in samples {
+            match self.nodes.get(index) {
+                Some(node) => nodes.push(node.clone()),
+                None => return Err(InlineAncestryError::InvalidNodeIndex { index }),
+            }
+        }
+        crate::util::validate_graph(&nodes, self.genome_length)
+    }
+
+    fn debug_assert_samples_cover_genome(&self) {
+        debug_assert!(
+            self.nodes
+                .iter()
+                .all(|n| crate::util::validate_covers_genome(n, self.genome_length)),
+            "sample node ancestry does not cover [0, {})",
+            self.genome_length
+        );
+    }
+
+    /// Add a mutation at `position` to the currently-alive node `who`.
+    ///
+    /// Returns the index of the new mutation in [`Population::mutations`].
+    pub fn add_mutation(
+        &mut self,
+        who: usize,
+        position: LargeSignedInteger,
+    ) -> Result<usize, InlineAncestryError> {
+        if position < 0 || position >= self.genome_length {
+            return Err(InlineAncestryError::InvalidPosition { p: position });
+        }
+        let node = match self.get(who) {
+            Some(node) => node.clone(),
+            None => return Err(InlineAncestryError::InvalidNodeIndex { index: who }),
+        };
+        self.mutations.push(Mutation::new(node, position));
+        Ok(self.mutations.len() - 1)
+    }
+
+    pub fn mutations(&self) -> &[Mutation] {
+        &self.mutations
+    }
+
+    /// `(index into the mutations, child birth time, parent birth time)`
+    /// for each mutation, where the child and parent are the
+    /// nodes of the edge above the mutation in its local tree.
+    ///
+    /// The mutation arose between the two birth times.
+    /// As in [`Population::to_tables`], a mutation is placed on the
+    /// node that its node's ancestry maps to at its position,
+    /// following the mapping through nodes that are no longer reachable.
+    /// Mutations dropped by [`Population::to_tables`], or above
+    /// the root of their local tree, are not included.
+    pub fn mutation_ages(&self) -> Vec<(usize, LargeSignedInteger, LargeSignedInteger)> {
+        let reachable = self.all_reachable_nodes();
+        let mut rv = vec![];
+        for (i, m) in self.mutations.iter().enumerate() {
+            let mapped = match crate::util::mutation_mapped_node(&m.node, m.position) {
+                Some(mapped) if reachable.contains(&mapped) => mapped,
+                _ => continue,
+            };
+            let child = mapped.borrow();
+            let parent = child.parents.iter().find(|p| {
+                p.borrow()
+                    .children
+                    .get(&mapped)
+                    .is_some_and(|segments| segments.iter().any(|s| s.contains(m.position)))
+            });
+            if let Some(parent) = parent {
+                rv.push((i, child.birth_time, parent.borrow().birth_time));
+            }
+        }
+        rv
+    }
+
+    /// The number of coalescence events resolved during the
+    /// most recent call to [`EvolveAncestry::simplify`].
+    ///
+    /// An event is one genomic interval of one ancestral node
+    /// where more than one child lineage overlaps.
+    /// Because simplification here is incremental, nodes
+    /// revisited by a later call contribute again to that call.
+    pub fn coalescences_last_simplify(&self) -> usize {
+        self.last_propagation_stats.coalescences
+    }
+
+    /// If `track` is `true`, each call to [`EvolveAncestry::simplify`]
+    /// records the number of nodes still reachable from the
+    /// alive nodes.
+    /// See [`Population::ancestor_count_history`].
+    pub fn track_ancestor_counts(&mut self, track: bool) {
+        self.track_ancestor_counts = track;
+    }
+
+    /// If `track` is `true`, each call to [`EvolveAncestry::simplify`]
+    /// counts the nodes that it frees.
+    /// See [`Population::last_freed_count`].
+    pub fn track_freed_counts(&mut self, track: bool) {
+        self.track_freed_counts = track;
+        if !track {
+            self.last_freed_count = None;
+        }
+    }
+
+    /// The number of nodes freed by the last call to
+    /// [`EvolveAncestry::simplify`], or `None` if it was
+    /// not tracked.
+    pub fn last_freed_count(&self) -> Option<usize> {
+        self.last_freed_count
+    }
+
+    // The nodes reachable from the alive nodes, the pending
+    // births, and nodes whose propagation is deferred,
+    // plus the nodes carrying mutations.
+    fn held_nodes(&self) -> HashSet<Node> {
+        let roots = self
+            .nodes
+            .iter()
+            .chain(self.births.iter())
+            .chain(self.deferred_deaths.iter())
+            .chain(self.deferred_births.iter())
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut held = crate::util::all_reachable_nodes(&roots);
+        held.extend(self.mutations.iter().map(|m| m.node.clone()));
+        held
+    }
+
+    fn num_held_nodes(&self) -> usize {
+        self.held_nodes().len()
+    }
+
+    /// Renumber all nodes `0, 1, ...` in order of birth time
+    /// and then of their current index, and continue numbering
+    /// new births from there.
+    ///
+    /// Nodes born at the same time keep the relative order of
+    /// their current ids, so the result depends on how ids were
+    /// assigned as well as on the graph.  Two runs that record
+    /// the same births in the same order get the same ids.
+    /// The relative order of ids is unchanged, so the output of
+    /// [`Population::to_tables`] is also unchanged.
+    /// Nodes held only by the history recorded by
+    /// [`Population::record_full_history`] are renumbered too,
+    /// so that no two nodes share an id.
+    pub fn canonicalize_ids(&mut self) {
+        let mut held = self.held_nodes();
+        if let Some(history) = self.full_history.as_ref() {
+            let recorded = history
+                .initial_nodes
+                .iter()
+                .chain(
+                    history
+                        .generations
+                        .iter()
+                        .flat_map(|g| g.1.iter().map(|b| &b.1)),
+                )
+                .cloned()
+                .collect::<Vec<_>>();
+            held.extend(crate::util::all_reachable_nodes(&recorded));
+        }
+        let mut held = held.into_iter().collect::<Vec<_>>();
+        held.sort_by_key(|n| {
+            let b = n.borrow();
+            (b.birth_time, b.index)
+        });
+        let mut founder_metadata = HashMap::default();
+        for (i, node) in held.iter().enumerate() {
+            let mut b = node.borrow_mut();
+            if let Some(md) = self.founder_metadata.remove(&b.index) {
+                founder_metadata.insert(i as SignedInteger, md);
+            }
+            b.index = i as SignedInteger;
+        }
+        self.founder_metadata = founder_metadata;
+        self.next_node_id = held.len() as SignedInteger;
+    }
+
+    /// If `track` is `true`, each call to [`EvolveAncestry::simplify`]
+    /// records [`Population::num_edges`].
+    /// See [`Population::edge_count_history`].
+    pub fn track_edge_counts(&mut self, track: bool) {
+        self.track_edge_counts = track;
+    }
+
+    /// If `Some(k)`, [`EvolveAncestry::simplify`] propagates
+    /// ancestry changes after every `k` replacements rather than
+    /// once for all of them, bounding the size of the node heap.
+    /// The resulting graph does not depend on the batch size.
+    ///
+    /// # Panics
+    ///
+    /// If `batch_size` is `Some(0)`.
+    pub fn set_batch_size(&mut self, batch_size: Option<usize>) {
+        assert!(batch_size != Some(0));
+        self.batch_size = batch_size;
+    }
+
+    /// If `verify` is `true`, [`EvolveAncestry::simplify`] runs
+    /// [`Population::validate_graph`] and returns any error,
+    /// even in release builds.
+    /// Debug builds always validate.
+    pub fn verify_after_simplify(&mut self, verify: bool) {
+        self.verify_after_simplify = verify;
+    }
+
+    fn should_verify(&self) -> bool {
+        self.debug_validation || self.verify_after_simplify
+    }
+
+    /// `(time, number of reachable nodes)` for each simplification
+    /// done while tracking was enabled.
+    pub fn ancestor_count_history(&self) -> &[(LargeSignedInteger, usize)] {
+        &self.ancestor_count_history
+    }
+
+    /// `(time, number of edges)` for each simplification
+    /// done while tracking was enabled.
+    pub fn edge_count_history(&self) -> &[(LargeSignedInteger, usize)] {
+        &self.edge_count_history
+    }
+
+    /// Births recorded since the last simplification,
+    /// in the order they were recorded.
+    pub fn pending_births(&self) -> &[Node] {
+        &self.births
+    }
+
+    /// Mutable access to the births recorded since the last
+    /// simplification, e.g. to set flags before simplifying.
+    pub fn pending_births_mut(&mut self) -> &mut [Node] {
+        &mut self.births
+    }
+
+    /// Mark the alive node `who` to be replaced by a birth
+    /// at the next simplification.
+    ///
+    /// This is the explicit alternative to
+    /// [`EvolveAncestry::generate_deaths`], for use with
+    /// [`Population::record_birth_from_parents`].
+    pub fn record_death(&mut self, who: usize) -> Result<(), InlineAncestryError> {
+        if who >= self.nodes.len() || self.replacements.contains(&who) {
+            return Err(InlineAncestryError::InvalidNodeIndex { index: who });
+        }
+        self.replacements.push(who);
+        self.log_event(|| Event::Death { who });
+        Ok(())
+    }
+
+    /// Record a birth whose parents and inherited segments are known,
+    /// as in a pedigree.
+    ///
+    /// Each element of `parents` is `(parent index, left, right)`.
+    /// Together, the segments must tile `[0, genome_length)`
+    /// with no gaps or overlaps.
+    pub fn record_birth_from_parents(
+        &mut self,
+        birth_time: LargeSignedInteger,
+        parents: &[(usize, LargeSignedInteger, LargeSignedInteger)],
+    ) -> Result<(), InlineAncestryError> {
+        let mut segments = parents.to_vec();
+        segments.sort_by_key(|s| s.1);
+        let mut last_right = 0;
+        for &(parent, left, right) in segments.iter() {
+            if parent >= self.nodes.len() {
+                return Err(InlineAncestryError::InvalidNodeIndex { index: parent });
+            }
+            if left >= right {
+                return Err(InlineAncestryError::InvalidSegment { left, right });
+            }
+            if left != last_right {
+                return Err(InlineAncestryError::GenomeNotTiled {
+                    l: self.genome_length,
+                });
+            }
+            last_right = right;
+        }
+        if last_right != self.genome_length {
+            return Err(InlineAncestryError::GenomeNotTiled {
+                l: self.genome_length,
+            });
+        }
+
+        self.add_birth(birth_time, parents)
+    }
+
+    fn add_birth(
+        &mut self,
+        birth_time: LargeSignedInteger,
+        segments: &[(usize, LargeSignedInteger, LargeSignedInteger)],
+    ) -> Result<(), InlineAncestryError> {
+        // Check before changing any state: children
+        // must be strictly younger than their parents.
+        if let Some(parent_birth_time) = segments
+            .iter()
+            .map(|&(parent, _, _)| self.nodes[parent].borrow().birth_time)
+            .max()
+        {
+            if birth_time <= parent_birth_time {
+                return Err(InlineAncestryError::NonMonotonicBirth {
+                    birth_time,
+                    parent_birth_time,
+                });
+            }
+        }
+
+        // Give birth to a new Individual ("node")
+        let mut birth = self.birth(birth_time);
+        for &(parent, left, right) in segments {
+            self.add_transmission(&mut birth, parent, left, right)?;
+        }
+        assert!(!birth.borrow().parents.is_empty());
+
+        // MOVE the birth w/o increasing ref count
+        self.births.push(birth);
+        self.log_event(|| Event::Birth {
+            birth_time,
+            segments: segments.to_vec(),
+        });
+        Ok(())
+    }
+
+    /// If `defer` is `true`, [`EvolveAncestry::simplify`] only replaces
+    /// the dead nodes with the births.
+    /// Ancestry changes are then propagated once, for the whole
+    /// recorded history, by [`Population::propagate_deferred`]
+    /// or [`EvolveAncestry::finish`].
+    /// The result is the same as simplifying at every time step,
+    /// but the graph holds all unsimplified history until then.
+    pub fn defer_propagation(&mut self, defer: bool) {
+        self.defer_propagation = defer;
+    }
+
+    /// Propagate the ancestry changes of all deaths and births
+    /// deferred by [`Population::defer_propagation`].
+    pub fn propagate_deferred(&mut self) -> Result<(), InlineAncestryError> {
+        if self.deferred_deaths.is_empty() {
+            return Ok(());
+        }
+        assert!(self.node_heap.is_empty());
+        // A node born and then killed while propagation was
+        // deferred must enter the heap as a death, so deaths
+        // go first: a node is only added to the heap once.
+        for dead in self.deferred_deaths.drain(..) {
+            self.node_heap.push_death(dead)?;
+        }
+        for birth in self.deferred_births.drain(..) {
+            self.node_heap.push_birth(birth)?;
+        }
+        self.last_propagation_stats =
+            crate::propagate_ancestry_changes::propagate_ancestry_changes(
+                self.genome_length,
+                &mut self.node_heap,
+            )?;
+
+        if self.should_verify() {
+            self.validate_graph()?;
+        }
+        Ok(())
+    }
+
+    /// If `log` is `true`, record all later births, deaths, and
+    /// simplifications in an [`EventLog`].
+    /// If `false`, discard any existing log.
+    pub fn log_events(&mut self, log: bool) {
+        if !log {
+            self.event_log = None;
+        } else if self.event_log.is_none() {
+            self.event_log = Some(EventLog::new());
+        }
+    }
+
+    pub fn event_log(&self) -> Option<&EventLog> {
+        self.event_log.as_ref()
+    }
+
+    /// If `record` is `true`, record the births and replaced
+    /// indices of all later simplifications, so that
+    /// [`Population::alive_nodes_at`] can reconstruct past
+    /// generations.
+    /// If `false`, discard any existing history.
+    ///
+    /// The history holds every recorded birth, so the memory
+    /// used grows with the length of the simulation.
+    pub fn record_full_history(&mut self, record: bool) {
+        if !record {
+            self.full_history = None;
+        } else if self.full_history.is_none() {
+            self.full_history = Some(FullHistory {
+                initial_nodes: self.nodes.clone(),
+                generations: vec![],
+            });
+        }
+    }
+
+    /// The alive nodes after the last simplification at or
+    /// before time `t`.
+    ///
+    /// Only history recorded by [`Population::record_full_history`]
+    /// is available: earlier times give the nodes alive when
+    /// recording began.
+    /// Returns `None` if history is not being recorded.
+    pub fn alive_nodes_at(&self, t: LargeSignedInteger) -> Option<Vec<Node>> {
+        let history = self.full_history.as_ref()?;
+        let mut nodes = history.initial_nodes.clone();
+        for (_, replacements) in history
+            .generations
+            .iter()
+            .take_while(|(time, _)| *time <= t)
+        {
+            for (index, birth) in replacements {
+                nodes[*index] = birth.clone();
+            }
+        }
+        Some(nodes)
+    }
+
+    /// Apply the events in `log` to a new population,
+    /// reproducing the population that recorded them.
+    ///
+    /// `popsize` and `genome_length` must match the
+    /// values used to create the logged population.
+    pub fn replay(
+        log: &EventLog,
+        popsize: SignedInteger,
+        genome_length: LargeSignedInteger,
+    ) -> Result<Population, InlineAncestryError> {
+        let mut pop = Population::new(popsize, genome_length)?;
+        for event in log.events() {
+            match event {
+                Event::Death { who } => pop.record_death(*who)?,
+                Event::Birth {
+                    birth_time,
+                    segments,
+                } => pop.add_birth(*birth_time, segments)?,
+                Event::Simplify { time } => pop.simplify_details(*time)?,
+            }
+        }
+        Ok(pop)
+    }
+
+    fn log_event<F: FnOnce() -> Event>(&mut self, event: F) {
+        if let Some(log) = self.event_log.as_mut() {
+            log.push(event());
+        }
+    }
+
+    fn simplify_details(
+        &mut self,
+        current_time_point: LargeSignedInteger,
+    ) -> Result<(), InlineAncestryError> {
+        if self.replacements.len() != self.births.len() {
+            return Err(InlineAncestryError::BirthDeathMismatch {
+                deaths: self.replacements.len(),
+                births: self.births.len(),
+            });
+        }
+        assert!(self.node_heap.is_empty());
+        let num_held_before = if self.track_freed_counts {
+            Some(self.num_held_nodes())
+        } else {
+            None
+        };
+
+        let batch_size = self.batch_size.unwrap_or(self.replacements.len()).max(1);
+        self.last_propagation_stats = PropagationStats::default();
+        for (deaths, births) in self
+            .replacements
+            .chunks(batch_size)
+            .zip(self.births.chunks(batch_size))
+        {
+            for (death, birth) in deaths.iter().zip(births.iter()) {
+                let dead = self.nodes[*death].clone();
+                assert_eq!(birth.borrow().birth_time, current_time_point);
+                assert!(self.nodes[*death].is_alive());
+                if self.defer_propagation {
+                    self.deferred_deaths.push(dead);
+                    self.deferred_births.push(birth.clone());
+                } else {
+                    self.node_heap.push_death(dead)?;
+                    self.node_heap.push_birth(birth.clone())?;
+                }
+
+                self.nodes[*death] = birth.clone();
+            }
+
+            if !self.defer_propagation {
+                let stats = crate::propagate_ancestry_changes::propagate_ancestry_changes(
+                    self.genome_length,
+                    &mut self.node_heap,
+                )?;
+                self.last_propagation_stats.popped += stats.popped;
+                self.last_propagation_stats.coalescences += stats.coalescences;
+            }
+        }
+
+        if let Some(history) = self.full_history.as_mut() {
+            history.generations.push((
+                current_time_point,
+                self.replacements
+                    .iter()
+                    .copied()
+                    .zip(self.births.iter().cloned())
+                    .collect(),
+            ));
+        }
+        self.replacements.clear();
+        self.births.clear();
+
+        if !self.defer_propagation && self.should_verify() {
+            self.validate_graph()?;
+        }
+
+        assert!(self.node_heap.is_empty());
+
+        self.log_event(|| Event::Simplify {
+            time: current_time_point,
+        });
+        if self.track_ancestor_counts {
+            self.ancestor_count_history
+                .push((current_time_point, self.num_still_reachable()));
+        }
+        // Simplification does not create nodes,
+        // so the nodes held afterwards were held before.
+        self.last_freed_count = num_held_before.map(|n| n - self.num_held_nodes());
+        if self.track_edge_counts {
+            self.edge_count_history
+                .push((current_time_point, self.num_edges()));
+        }
+        Ok(())
+    }
+
+    fn add_transmission(
+        &mut self,
+        birth: &mut Node,
+        parent: usize,
+        left: LargeSignedInteger,
+        right: LargeSignedInteger,
+    ) -> Result<(), InlineAncestryError> {
+        // Increase ref count of parent
+        let mut parent = self.nodes[parent].clone();
+
+        // Add references to birth for each segment
+        parent.add_child_segment(left, right, birth.clone())?;
+        // MOVE parent w/o increasing ref count
+        birth.add_parent(parent)
+    }
 }
 
 impl EvolveAncestry for Population {
@@ -96,6 +988,7 @@ impl EvolveAncestry for Population {
         for i in 0..self.nodes.len() {
             if death.dies() {
                 self.replacements.push(i);
+                self.log_event(|| Event::Death { who: i });
             }
         }
 
@@ -113,64 +1006,29 @@ impl EvolveAncestry for Population {
         breakpoints: &[neutral_evolution::TransmittedSegment],
     ) -> Result<(), Box<dyn std::error::Error>> {
         assert!(!breakpoints.is_empty());
-        // Give birth to a new Individual ("node")
-        let mut birth = self.birth(birth_time);
-
         for b in breakpoints {
-            // Increase ref count of parent
-            let mut parent = self.get_mut(b.parent).as_mut().unwrap().clone();
-
-            // Add references to birth for each segment
-            parent.add_child_segment(b.left, b.right, birth.clone())?;
-            // MOVE parent w/o increasing ref count
-            birth.add_parent(parent)?;
+            b.validate()?;
         }
-
-        assert!(!birth.borrow().parents.is_empty());
-
-        // MOVE the birth w/o increasing ref count
-        self.births.push(birth);
-        Ok(())
+        let segments = breakpoints
+            .iter()
+            .map(|b| (b.parent, b.left, b.right))
+            .collect::<Vec<_>>();
+        Ok(self.add_birth(birth_time, &segments)?)
     }
 
     fn simplify(
         &mut self,
         current_time_point: LargeSignedInteger,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        assert_eq!(self.replacements.len(), self.births.len());
-        assert!(self.node_heap.is_empty());
-
-        for (i, death) in self.replacements.iter().enumerate() {
-            let dead = self.nodes[*death].clone();
-            let birth = self.births[i].clone();
-            assert_eq!(self.births[i].borrow().birth_time, current_time_point);
-            assert!(self.nodes[*death].is_alive());
-            self.node_heap.push_death(dead)?;
-            self.node_heap.push_birth(birth.clone())?;
-
-            self.nodes[*death] = birth;
-        }
-
-        self.births.clear();
-
-        let _poppped = crate::propagate_ancestry_changes::propagate_ancestry_changes(
-            self.genome_length,
-            &mut self.node_heap,
-        )?;
-
-        #[cfg(debug_assertions)]
-        {
-            self.validate_graph()?;
-        }
-
-        assert!(self.node_heap.is_empty());
-        Ok(())
+        Ok(self.simplify_details(current_time_point)?)
     }
 
     fn finish(
         &mut self,
         _current_time_point: LargeSignedInteger,
     ) -> Result<(), Box<dyn std::error::Error>> {
+        self.propagate_deferred()?;
+        self.debug_assert_samples_cover_genome();
         Ok(())
     }
 }
@@ -179,54 +1037,782 @@ impl TryFrom<Population> for tskit::TableCollection {
     type Error = crate::InlineAncestryError;
 
     fn try_from(value: Population) -> Result<Self, Self::Error> {
-        let mut tables = match tskit::TableCollection::new(value.genome_length() as f64) {
-            Ok(tables) => tables,
-            Err(e) => return Err(crate::InlineAncestryError::TskitError(e)),
-        };
+        value.to_tables()
+    }
+}
 
-        let mut node_map = std::collections::HashMap::<_, _>::default();
-        let mut max_time: LargeSignedInteger = 0;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use neutral_evolution::TransmittedSegment;
 
-        for i in value.all_reachable_nodes() {
-            max_time = std::cmp::max(max_time, i.borrow().birth_time);
-            node_map.insert(i.clone(), tskit::NodeId::NULL);
+    #[test]
+    fn test_mutations_on_pruned_nodes_are_dropped() {
+        let mut pop = Population::new(2, 10).unwrap();
+        let kept = pop.add_mutation(0, 3).unwrap();
+        let pruned = pop.add_mutation(1, 7).unwrap();
+        assert_eq!(pop.mutations().len(), 2);
+
+        // Both founders die and are replaced by
+        // offspring of founder 0.
+        pop.replacements = vec![0, 1];
+        for _ in 0..2 {
+            pop.record_birth(1, 1, &[TransmittedSegment::new(0, 10, 0)])
+                .unwrap();
         }
+        pop.simplify(1).unwrap();
+        // Founder 0 is the MRCA of both offspring on [0, 10)
+        assert_eq!(pop.coalescences_last_simplify(), 1);
 
-        for (k, v) in node_map.iter_mut() {
-            let birth_time = (-1_i64 * (k.borrow().birth_time - max_time)) as f64;
-            *v = match tables.add_node(0, birth_time, -1, -1) {
-                Ok(node_id) => node_id,
-                Err(e) => return Err(crate::InlineAncestryError::TskitError(e)),
-            };
+        let reachable = pop.all_reachable_nodes();
+        assert!(reachable.contains(&pop.mutations()[kept].node));
+        assert!(!reachable.contains(&pop.mutations()[pruned].node));
+
+        let tables = tskit::TableCollection::try_from(pop).unwrap();
+        assert_eq!(tables.sites().num_rows(), 1);
+        assert_eq!(tables.mutations().num_rows(), 1);
+        let site = tables.sites_iter().next().unwrap();
+        assert_eq!(site.position, 3.0);
+    }
+
+    #[test]
+    fn test_mutations_on_unary_nodes_are_remapped() {
+        let mut pop = Population::new(3, 10).unwrap();
+        for i in 0..3 {
+            pop.record_death(i).unwrap();
+        }
+        for parent in [0, 0, 1] {
+            pop.record_birth_from_parents(1, &[(parent, 0, 10)])
+                .unwrap();
+        }
+        pop.simplify(1).unwrap();
+        // On node 4, which will be unary
+        let unary = pop.add_mutation(1, 5).unwrap();
+        for i in 0..3 {
+            pop.record_death(i).unwrap();
+        }
+        for parent in [0, 0, 1] {
+            pop.record_birth_from_parents(2, &[(parent, 0, 10)])
+                .unwrap();
+        }
+        pop.simplify(2).unwrap();
+        assert!(!pop
+            .all_reachable_nodes()
+            .contains(&pop.mutations()[unary].node));
+
+        // The mutation moves to node 4's only child, node 8,
+        // which is alive.
+        let tables = pop.to_tables().unwrap();
+        assert_eq!(tables.mutations().num_rows(), 1);
+        let mutation = tables.mutations_iter().next().unwrap();
+        assert!(tables.nodes().flags(mutation.node).unwrap().is_sample());
+        assert_eq!(f64::from(tables.nodes().time(mutation.node).unwrap()), 0.0);
+        assert_eq!(f64::from(mutation.time), 0.0);
+    }
+
+    #[test]
+    fn test_record_birth_from_parents() {
+        let mut pop = Population::new(2, 10).unwrap();
+        pop.record_death(0).unwrap();
+        pop.record_death(1).unwrap();
+        assert!(pop.record_death(1).is_err());
+
+        // Full sibs: each inherits [0, 5) from 0 and [5, 10) from 1
+        for _ in 0..2 {
+            pop.record_birth_from_parents(1, &[(1, 5, 10), (0, 0, 5)])
+                .unwrap();
+        }
+        pop.simplify(1).unwrap();
+        assert_eq!(pop.num_still_reachable(), 4);
+        assert_eq!(pop.coalescences_last_simplify(), 2);
+        for i in 0..2 {
+            assert_eq!(pop.get(i).unwrap().borrow().parents.len(), 2);
         }
+    }
+
+    #[test]
+    fn test_validate_sample_ancestry() {
+        let pop = Population::new(3, 10).unwrap();
+        // Node 2 is disconnected from nodes 0 and 1
+        let mut corrupt = pop.nodes[2].clone();
+        corrupt
+            .add_child_segment(0, 10, Node::new_alive(100, 1))
+            .unwrap();
+        assert!(matches!(
+            pop.validate_graph(),
+            Err(InlineAncestryError::UnreachableChild)
+        ));
+        pop.validate_sample_ancestry(&[0, 1]).unwrap();
+        assert!(matches!(
+            pop.validate_sample_ancestry(&[0, 2]),
+            Err(InlineAncestryError::UnreachableChild)
+        ));
+        assert!(matches!(
+            pop.validate_sample_ancestry(&[3]),
+            Err(InlineAncestryError::InvalidNodeIndex { index: 3 })
+        ));
+    }
+
+    #[test]
+    fn test_complete_turnover() {
+        let mut pop = Population::new(4, 10).unwrap();
+        for i in 0..4 {
+            pop.record_death(i).unwrap();
+        }
+        // Not enough births
+        pop.record_birth_from_parents(1, &[(0, 0, 10)]).unwrap();
+        assert!(matches!(
+            pop.simplify(1)
+                .unwrap_err()
+                .downcast_ref::<InlineAncestryError>(),
+            Some(InlineAncestryError::BirthDeathMismatch {
+                deaths: 4,
+                births: 1
+            })
+        ));
+
+        let mut pop = Population::new(4, 10).unwrap();
+        for i in 0..4 {
+            pop.record_death(i).unwrap();
+        }
+        for i in 0..4 {
+            pop.record_birth_from_parents(1, &[(i % 2, 0, 5), (2, 5, 10)])
+                .unwrap();
+        }
+        pop.simplify(1).unwrap();
+        pop.validate_graph().unwrap();
+        assert_eq!(pop.current_sample_ids(), vec![4, 5, 6, 7]);
+        assert!(pop.nodes.iter().all(|n| n.borrow().birth_time == 1));
+        assert!(pop
+            .nodes
+            .iter()
+            .all(|n| crate::util::validate_covers_genome(n, 10)));
+
+        let tables = pop.to_tables().unwrap();
+        assert_eq!(tables.nodes().num_rows(), 7);
+        let samples = tables.nodes_iter().filter(|n| n.flags.is_sample()).count();
+        assert_eq!(samples, 4);
+        let _ = tables
+            .tree_sequence(tskit::TreeSequenceFlags::default())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_unreachable_nodes_are_freed() {
+        let mut pop = Population::new(2, 10).unwrap();
+        // The alive node's ancestry maps to itself
+        assert_eq!(std::rc::Rc::strong_count(&pop.nodes[1]), 2);
+        let founders = pop
+            .nodes
+            .iter()
+            .map(|n| std::rc::Rc::downgrade(n))
+            .collect::<Vec<_>>();
+        pop.record_death(0).unwrap();
+        pop.record_death(1).unwrap();
+        for _ in 0..2 {
+            pop.record_birth_from_parents(1, &[(0, 0, 10)]).unwrap();
+        }
+        pop.simplify(1).unwrap();
+        assert!(founders[0].upgrade().is_some());
+        assert!(founders[1].upgrade().is_none());
 
-        for i in value.all_reachable_nodes() {
-            let pid = node_map.get(&i).unwrap();
-            for (k, v) in i.borrow().children.iter() {
-                let cid = node_map.get(&k).unwrap();
-                for j in v {
-                    match tables.add_edge(j.left as f64, j.right as f64, *pid, *cid) {
-                        Ok(_) => (),
-                        Err(e) => return Err(crate::InlineAncestryError::TskitError(e)),
+        // Track every node created, founders and births,
+        // over a long run with overlapping generations.
+        use rand::Rng;
+        use rand::SeedableRng;
+        for seed in [101, 202, 303] {
+            let mut rng = rand_pcg::Pcg64::seed_from_u64(seed);
+            let mut pop = Population::new(10, 100).unwrap();
+            let mut created = pop
+                .nodes
+                .iter()
+                .map(|n| std::rc::Rc::downgrade(n))
+                .collect::<Vec<_>>();
+            for birth_time in 1..201 {
+                let deaths = (0..10).filter(|_| rng.gen_bool(0.5)).collect::<Vec<_>>();
+                for &i in &deaths {
+                    pop.record_death(i).unwrap();
+                }
+                for _ in &deaths {
+                    let p1 = rng.gen_range(0..10);
+                    let p2 = rng.gen_range(0..10);
+                    let breakpoint = rng.gen_range(1..100);
+                    if p1 == p2 {
+                        pop.record_birth_from_parents(birth_time, &[(p1, 0, 100)])
+                    } else {
+                        pop.record_birth_from_parents(
+                            birth_time,
+                            &[(p1, 0, breakpoint), (p2, breakpoint, 100)],
+                        )
                     }
+                    .unwrap();
+                }
+                created.extend(
+                    pop.pending_births()
+                        .iter()
+                        .map(|n| std::rc::Rc::downgrade(n)),
+                );
+                pop.simplify(birth_time).unwrap();
+            }
+            assert!(created.len() > 500);
+
+            let reachable = pop
+                .all_reachable_nodes()
+                .iter()
+                .map(|n| std::rc::Rc::as_ptr(n))
+                .collect::<HashSet<_>>();
+            let mut num_freed = 0;
+            for node in &created {
+                if reachable.contains(&node.as_ptr()) {
+                    assert!(node.strong_count() > 0);
+                } else {
+                    assert_eq!(node.strong_count(), 0, "seed {}", seed);
+                    num_freed += 1;
                 }
             }
+            assert_eq!(num_freed, created.len() - reachable.len());
         }
+    }
 
-        for i in value.nodes.iter() {
-            let node = node_map.get(i).unwrap();
-            tables.nodes().flags_array_mut()[usize::from(*node)] = tskit::NodeFlags::IS_SAMPLE;
+    #[test]
+    fn test_batched_simplification() {
+        for pdeath in [0.5, 1.0] {
+            let p = neutral_evolution::Parameters::new(pdeath, 1e-1, 50).unwrap();
+            let mut pop = Population::new(10, 100).unwrap();
+            neutral_evolution::evolve(101, p, &mut pop).unwrap();
+            let tables = pop.to_tables().unwrap();
+            for batch_size in [1, 3, 10] {
+                let mut batched = Population::new(10, 100).unwrap();
+                batched.set_batch_size(Some(batch_size));
+                neutral_evolution::evolve(101, p, &mut batched).unwrap();
+                assert_eq!(batched.current_sample_ids(), pop.current_sample_ids());
+                let batched_tables = batched.to_tables().unwrap();
+                assert!(tables.equals(&batched_tables, tskit::TableEqualityOptions::default()));
+            }
         }
+    }
 
-        match tables.full_sort(tskit::TableSortOptions::default()) {
-            Ok(_) => (),
-            Err(e) => return Err(crate::InlineAncestryError::TskitError(e)),
+    #[test]
+    fn test_pending_births() {
+        let mut pop = Population::new(2, 10).unwrap();
+        assert!(pop.pending_births().is_empty());
+        pop.record_death(1).unwrap();
+        pop.record_birth_from_parents(1, &[(0, 0, 10)]).unwrap();
+        assert_eq!(pop.pending_births().len(), 1);
+        assert_eq!(pop.pending_births()[0].borrow().index, 2);
+        let birth = pop.pending_births_mut()[0].clone();
+        pop.simplify(1).unwrap();
+        assert!(pop.pending_births().is_empty());
+        assert!(pop.nodes[1] == birth);
+    }
+
+    #[test]
+    fn test_replay_event_log() {
+        for pdeath in [0.5, 1.0] {
+            let p = neutral_evolution::Parameters::new(pdeath, 1e-1, 20).unwrap();
+            let mut pop = Population::new(10, 100).unwrap();
+            assert!(pop.event_log().is_none());
+            pop.log_events(true);
+            neutral_evolution::evolve(101, p, &mut pop).unwrap();
+
+            let log = pop.event_log().unwrap();
+            let num_simplifications = log
+                .events()
+                .iter()
+                .filter(|e| matches!(e, Event::Simplify { .. }))
+                .count();
+            assert_eq!(num_simplifications, 20);
+
+            let replayed = Population::replay(log, 10, 100).unwrap();
+            assert!(replayed.event_log().is_none());
+            assert_eq!(replayed.current_sample_ids(), pop.current_sample_ids());
+            let tables = pop.to_tables().unwrap();
+            let replayed_tables = replayed.to_tables().unwrap();
+            assert!(tables.equals(&replayed_tables, tskit::TableEqualityOptions::default()));
         }
+    }
 
-        match tables.build_index() {
-            Ok(_) => (),
-            Err(e) => return Err(crate::InlineAncestryError::TskitError(e)),
+    #[test]
+    fn test_alive_nodes_at() {
+        let ids = |nodes: &[Node]| nodes.iter().map(|n| n.borrow().index).collect::<Vec<_>>();
+        for pdeath in [0.5, 1.0] {
+            let mut pop = Population::new(10, 100).unwrap();
+            assert!(pop.alive_nodes_at(0).is_none());
+            pop.record_full_history(true);
+            let p = neutral_evolution::Parameters::new(pdeath, 1e-1, 20).unwrap();
+            neutral_evolution::evolve(101, p, &mut pop).unwrap();
+
+            // Same seed, stopped at an interior generation
+            let mut reference = Population::new(10, 100).unwrap();
+            let p = neutral_evolution::Parameters::new(pdeath, 1e-1, 8).unwrap();
+            neutral_evolution::evolve(101, p, &mut reference).unwrap();
+
+            assert_eq!(ids(&pop.alive_nodes_at(8).unwrap()), ids(&reference.nodes));
+            assert_eq!(
+                ids(&pop.alive_nodes_at(0).unwrap()),
+                (0..10).collect::<Vec<_>>()
+            );
+            assert_eq!(ids(&pop.alive_nodes_at(20).unwrap()), ids(&pop.nodes));
+
+            pop.record_full_history(false);
+            assert!(pop.alive_nodes_at(8).is_none());
         }
-        Ok(tables)
+    }
+
+    #[test]
+    fn test_mutation_ages() {
+        let mut pop = Population::new(3, 10).unwrap();
+        // Node 0 is the root
+        pop.add_mutation(0, 1).unwrap();
+        for i in 0..3 {
+            pop.record_death(i).unwrap();
+        }
+        for parent in [0, 0, 1] {
+            pop.record_birth_from_parents(1, &[(parent, 0, 10)])
+                .unwrap();
+        }
+        pop.simplify(1).unwrap();
+        // Node 3 will have two children
+        pop.add_mutation(0, 5).unwrap();
+        // Node 4 will be unary, so its mutation moves down to node 8,
+        // whose parent is then node 0. Node 5 will leave no descendants.
+        pop.add_mutation(1, 5).unwrap();
+        pop.add_mutation(2, 5).unwrap();
+        for i in 0..3 {
+            pop.record_death(i).unwrap();
+        }
+        for parent in [0, 0, 1] {
+            pop.record_birth_from_parents(2, &[(parent, 0, 10)])
+                .unwrap();
+        }
+        pop.simplify(2).unwrap();
+        pop.add_mutation(0, 2).unwrap();
+        assert_eq!(pop.mutation_ages(), vec![(1, 1, 0), (2, 2, 0), (4, 2, 1)]);
+    }
+
+    #[test]
+    fn test_zero_length_transmission() {
+        use neutral_evolution::{TransmissionError, TransmittedSegment};
+
+        let mut pop = Population::new(2, 10).unwrap();
+        let e = pop
+            .record_birth(
+                1,
+                1,
+                &[
+                    TransmittedSegment::new(0, 5, 0),
+                    TransmittedSegment::new(5, 5, 1),
+                    TransmittedSegment::new(5, 10, 1),
+                ],
+            )
+            .unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<TransmissionError>(),
+            Some(&TransmissionError::ZeroLengthSegment { position: 5 })
+        );
+        assert!(pop.pending_births().is_empty());
+        assert_eq!(pop.next_node_id, 2);
+    }
+
+    #[test]
+    fn test_deferred_propagation_matches_incremental() {
+        for pdeath in [0.25, 0.5, 1.0] {
+            for seed in [101, 8512389] {
+                let p = neutral_evolution::Parameters::new(pdeath, 1e-1, 50).unwrap();
+                let mut pop = Population::new(10, 100).unwrap();
+                neutral_evolution::evolve(seed, p, &mut pop).unwrap();
+
+                let mut deferred = Population::new(10, 100).unwrap();
+                deferred.defer_propagation(true);
+                neutral_evolution::evolve(seed, p, &mut deferred).unwrap();
+                assert!(deferred.deferred_deaths.is_empty());
+
+                assert_eq!(deferred.num_still_reachable(), pop.num_still_reachable());
+                let tables = pop.to_tables().unwrap();
+                let deferred_tables = deferred.to_tables().unwrap();
+                assert!(
+                    tables.equals(&deferred_tables, tskit::TableEqualityOptions::default()),
+                    "{} {}",
+                    pdeath,
+                    seed
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_to_tables_time_direction() {
+        let p = neutral_evolution::Parameters::new(0.5, 1e-1, 20).unwrap();
+        let mut pop = Population::new(10, 100).unwrap();
+        neutral_evolution::evolve(101, p, &mut pop).unwrap();
+        for time_direction in [TimeDirection::Backward, TimeDirection::Forward] {
+            let tables = pop
+                .to_tables_with(TableConversionOptions { time_direction })
+                .unwrap();
+            let time = |n| f64::from(tables.nodes().time(n).unwrap());
+            for e in tables.edges_iter() {
+                assert!(time(e.parent) > time(e.child));
+            }
+            let reachable = pop.reachable_nodes_sorted();
+            for (i, node) in reachable.iter().enumerate() {
+                let birth_time = node.borrow().birth_time as f64;
+                let expected = match time_direction {
+                    TimeDirection::Backward => 20.0 - birth_time,
+                    TimeDirection::Forward => -birth_time,
+                };
+                assert_eq!(time(tskit::NodeId::from(i as i32)), expected);
+            }
+            let _ = tables
+                .tree_sequence(tskit::TreeSequenceFlags::default())
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_ancestor_count_history() {
+        let p = neutral_evolution::Parameters::new(0.5, 1e-1, 10).unwrap();
+
+        let mut pop = Population::new(10, 100).unwrap();
+        neutral_evolution::evolve(54321, p, &mut pop).unwrap();
+        assert!(pop.ancestor_count_history().is_empty());
+
+        let mut pop = Population::new(10, 100).unwrap();
+        pop.track_ancestor_counts(true);
+        neutral_evolution::evolve(54321, p, &mut pop).unwrap();
+        let history = pop.ancestor_count_history();
+        assert_eq!(history.len(), 10);
+        for (i, (time, count)) in history.iter().enumerate() {
+            assert_eq!(*time, i as LargeSignedInteger + 1);
+            assert!(*count >= pop.len());
+        }
+        assert_eq!(history.last().unwrap().1, pop.num_still_reachable());
+    }
+
+    #[test]
+    fn test_last_freed_count() {
+        let mut pop = Population::new(3, 10).unwrap();
+        for i in 0..3 {
+            pop.record_death(i).unwrap();
+            pop.record_birth_from_parents(1, &[(0, 0, 10)]).unwrap();
+        }
+        pop.simplify(1).unwrap();
+        assert_eq!(pop.last_freed_count(), None);
+
+        pop.track_freed_counts(true);
+        // Each of the three dead nodes has one child,
+        // so it is unary and is freed.
+        for i in 0..3 {
+            pop.record_death(i).unwrap();
+            pop.record_birth_from_parents(2, &[(i, 0, 10)]).unwrap();
+        }
+        pop.simplify(2).unwrap();
+        assert_eq!(pop.last_freed_count(), Some(3));
+
+        // Only the lineage of node 0 survives: the other two
+        // dead nodes are freed, along with the founder, which
+        // is now unary.
+        for i in 0..3 {
+            pop.record_death(i).unwrap();
+            pop.record_birth_from_parents(3, &[(0, 0, 10)]).unwrap();
+        }
+        pop.simplify(3).unwrap();
+        assert_eq!(pop.last_freed_count(), Some(3));
+
+        pop.track_freed_counts(false);
+        assert_eq!(pop.last_freed_count(), None);
+    }
+
+    #[test]
+    fn test_birth_older_than_parent() {
+        let mut pop = Population::new(2, 10).unwrap();
+        pop.record_death(0).unwrap();
+        pop.record_birth_from_parents(1, &[(0, 0, 10)]).unwrap();
+        pop.simplify(1).unwrap();
+
+        pop.record_death(1).unwrap();
+        for birth_time in [0, 1] {
+            assert!(matches!(
+                pop.record_birth_from_parents(birth_time, &[(1, 0, 5), (0, 5, 10)]),
+                Err(InlineAncestryError::NonMonotonicBirth {
+                    parent_birth_time: 1,
+                    ..
+                })
+            ));
+        }
+        assert!(pop.pending_births().is_empty());
+        assert!(pop.get(0).unwrap().borrow().children.is_empty());
+        assert!(pop.get(1).unwrap().borrow().children.is_empty());
+    }
+
+    #[test]
+    fn test_canonicalize_ids() {
+        let run = || {
+            let p = neutral_evolution::Parameters::new(0.5, 1e-1, 20).unwrap();
+            let mut pop = Population::new(10, 100).unwrap();
+            neutral_evolution::evolve(101, p, &mut pop).unwrap();
+            pop
+        };
+        let mut pop = run();
+        let tables = pop.to_tables().unwrap();
+        pop.canonicalize_ids();
+        let reachable = pop.reachable_nodes_sorted();
+        for (i, node) in reachable.iter().enumerate() {
+            assert_eq!(node.borrow().index, i as SignedInteger);
+        }
+        assert_eq!(pop.next_node_id, reachable.len() as SignedInteger);
+        pop.validate_graph().unwrap();
+        assert!(tables.equals(
+            &pop.to_tables().unwrap(),
+            tskit::TableEqualityOptions::default()
+        ));
+
+        let mut edges = vec![];
+        pop.for_each_edge(|p, c, l, r| edges.push((p, c, l, r)));
+        let mut other = run();
+        other.canonicalize_ids();
+        let mut other_edges = vec![];
+        other.for_each_edge(|p, c, l, r| other_edges.push((p, c, l, r)));
+        edges.sort_unstable();
+        other_edges.sort_unstable();
+        assert_eq!(edges, other_edges);
+        assert_eq!(pop.current_sample_ids(), other.current_sample_ids());
+
+        // New births continue from the canonical ids.
+        pop.record_death(0).unwrap();
+        pop.record_birth_from_parents(21, &[(1, 0, 100)]).unwrap();
+        assert_eq!(
+            pop.pending_births()[0].borrow().index,
+            reachable.len() as SignedInteger
+        );
+
+        // Nodes only held by the recorded history are renumbered.
+        let p = neutral_evolution::Parameters::new(0.5, 1e-1, 20).unwrap();
+        let mut pop = Population::new(10, 100).unwrap();
+        pop.record_full_history(true);
+        neutral_evolution::evolve(101, p, &mut pop).unwrap();
+        pop.canonicalize_ids();
+        let mut nodes = pop.all_reachable_nodes();
+        for t in 0..21 {
+            nodes.extend(pop.alive_nodes_at(t).unwrap());
+        }
+        assert!(nodes.len() > pop.num_still_reachable());
+        let mut ids = nodes.iter().map(|n| n.borrow().index).collect::<Vec<_>>();
+        ids.sort_unstable();
+        assert_eq!(ids, (0..nodes.len() as SignedInteger).collect::<Vec<_>>());
+        assert_eq!(pop.next_node_id, nodes.len() as SignedInteger);
+    }
+
+    #[test]
+    fn test_edge_count_history() {
+        let p = neutral_evolution::Parameters::new(0.5, 1e-1, 10).unwrap();
+
+        let mut pop = Population::new(10, 100).unwrap();
+        neutral_evolution::evolve(54321, p, &mut pop).unwrap();
+        assert!(pop.edge_count_history().is_empty());
+
+        let mut pop = Population::new(10, 100).unwrap();
+        pop.track_edge_counts(true);
+        neutral_evolution::evolve(54321, p, &mut pop).unwrap();
+        let history = pop.edge_count_history();
+        assert_eq!(history.len(), 10);
+        for (i, (time, _)) in history.iter().enumerate() {
+            assert_eq!(*time, i as LargeSignedInteger + 1);
+        }
+        let num_edges = pop.num_edges();
+        assert_eq!(history.last().unwrap().1, num_edges);
+        let tables = pop.to_tables().unwrap();
+        assert_eq!(u64::from(tables.edges().num_rows()), num_edges as u64);
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Deme(u8);
+
+    impl tskit::metadata::MetadataRoundtrip for Deme {
+        fn encode(&self) -> Result<Vec<u8>, tskit::metadata::MetadataError> {
+            Ok(vec![self.0])
+        }
+
+        fn decode(md: &[u8]) -> Result<Self, tskit::metadata::MetadataError> {
+            Ok(Self(md[0]))
+        }
+    }
+
+    impl tskit::metadata::NodeMetadata for Deme {}
+
+    #[test]
+    fn test_founder_metadata() {
+        assert!(matches!(
+            Population::new_with_founder_metadata(10, 100, vec![Deme(0)]),
+            Err(InlineAncestryError::FounderMetadataLength {
+                popsize: 10,
+                len: 1
+            })
+        ));
+
+        let metadata = (0..10).map(|i| Deme(i % 2)).collect::<Vec<_>>();
+        let mut pop = Population::new_with_founder_metadata(10, 100, metadata).unwrap();
+        let p = neutral_evolution::Parameters::new(0.5, 1e-1, 3).unwrap();
+        neutral_evolution::evolve(101, p, &mut pop).unwrap();
+
+        let founders = pop
+            .reachable_nodes_sorted()
+            .iter()
+            .map(|n| n.borrow().index)
+            .filter(|i| *i < 10)
+            .collect::<Vec<_>>();
+        assert!(!founders.is_empty());
+
+        let tables = pop.to_tables().unwrap();
+        // Nodes are added in the order of reachable_nodes_sorted,
+        // which puts the founders first.
+        for (i, node) in tables.nodes_iter().enumerate() {
+            let md = tables.nodes().metadata::<Deme>(node.id).unwrap();
+            match founders.get(i) {
+                Some(founder) => assert_eq!(md, Some(Deme(*founder as u8 % 2))),
+                None => assert!(md.is_none()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_into_parts_round_trip() {
+        let p = neutral_evolution::Parameters::new(0.5, 1e-1, 10).unwrap();
+        let mut pop = Population::new(5, 100).unwrap();
+        neutral_evolution::evolve(101, p, &mut pop).unwrap();
+        let ids = pop.current_sample_ids();
+        let tables = pop.to_tables().unwrap();
+
+        let (nodes, genome_length) = pop.into_parts();
+        assert_eq!(genome_length, 100);
+        let pop = Population::with_initial_nodes(nodes, genome_length).unwrap();
+        assert_eq!(pop.current_sample_ids(), ids);
+        assert_eq!(pop.next_node_id, ids.iter().max().unwrap() + 1);
+        let tables_again = pop.to_tables().unwrap();
+        assert!(tables.equals(&tables_again, tskit::TableEqualityOptions::default()));
+    }
+
+    #[test]
+    fn test_descendant_samples() {
+        let mut pop = Population::new(3, 10).unwrap();
+        let founder = pop.get(0).unwrap().clone();
+        for i in 0..3 {
+            pop.record_death(i).unwrap();
+        }
+        pop.record_birth_from_parents(1, &[(0, 0, 10)]).unwrap();
+        pop.record_birth_from_parents(1, &[(0, 0, 10)]).unwrap();
+        pop.record_birth_from_parents(1, &[(1, 0, 5), (0, 5, 10)])
+            .unwrap();
+        pop.simplify(1).unwrap();
+        let interior = pop.get(2).unwrap().clone();
+
+        // The interior node is the parent of two of the samples.
+        // The founder is only a coalescent ancestor on [5, 10).
+        for (i, parent) in [2, 2, 0].into_iter().enumerate() {
+            pop.record_death(i).unwrap();
+            pop.record_birth_from_parents(2, &[(parent, 0, 10)])
+                .unwrap();
+        }
+        pop.simplify(2).unwrap();
+
+        assert_eq!(
+            pop.descendant_samples(&founder),
+            vec![(0, 5, 10), (1, 5, 10), (2, 5, 10)]
+        );
+        assert_eq!(
+            pop.descendant_samples(&interior),
+            vec![(0, 0, 10), (1, 0, 10)]
+        );
+        assert!(pop.descendant_samples(pop.get(0).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_current_sample_ids() {
+        let mut pop = Population::new(5, 100).unwrap();
+        assert_eq!(pop.current_sample_ids(), vec![0, 1, 2, 3, 4]);
+
+        let p = neutral_evolution::Parameters::new(1.0, 1e-1, 3).unwrap();
+        neutral_evolution::evolve(101, p, &mut pop).unwrap();
+        let ids = pop.current_sample_ids();
+        assert_eq!(ids.len(), pop.len());
+        for (id, node) in ids.iter().zip(pop.nodes.iter()) {
+            assert_eq!(*id, node.borrow().index);
+            // Everyone has been replaced
+            assert!(*id >= 5);
+        }
+    }
+
+    #[test]
+    fn test_verify_after_simplify() {
+        let run = |verify: bool| {
+            let p = neutral_evolution::Parameters::new(0.5, 1e-1, 5).unwrap();
+            let mut pop = Population::new(10, 100).unwrap();
+            // Behave as a release build.
+            pop.debug_validation = false;
+            pop.verify_after_simplify(verify);
+            neutral_evolution::evolve(101, p, &mut pop).unwrap();
+            pop
+        };
+        // Corrupt the graph by giving an alive node
+        // a child that is not reachable.
+        let add_unreachable_child = |pop: &Population| {
+            pop.get(0).unwrap().borrow_mut().children.insert(
+                Node::new_alive(1000, 5),
+                vec![Segment::new(0, 100).unwrap()],
+            );
+        };
+
+        let mut pop = run(false);
+        add_unreachable_child(&pop);
+        assert!(pop.simplify_details(6).is_ok());
+
+        let mut pop = run(true);
+        add_unreachable_child(&pop);
+        assert!(matches!(
+            pop.simplify_details(6),
+            Err(InlineAncestryError::UnreachableChild)
+        ));
+
+        // Corrupt the ancestry of an alive node.
+        let mut pop = run(true);
+        let index = pop.get(0).unwrap().borrow().index;
+        pop.get(0).unwrap().borrow_mut().ancestry[0].segment.right = 50;
+        assert!(matches!(
+            pop.simplify_details(6),
+            Err(InlineAncestryError::InvalidAliveAncestry { index: i }) if i == index
+        ));
+    }
+
+    #[test]
+    fn test_validate_covers_genome() {
+        let pop = Population::new(1, 10).unwrap();
+        let node = pop.get(0).unwrap();
+        assert!(crate::util::validate_covers_genome(node, 10));
+        assert!(!crate::util::validate_covers_genome(node, 11));
+
+        node.borrow_mut().ancestry[0].segment.right = 5;
+        assert!(!crate::util::validate_covers_genome(node, 10));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic]
+    fn test_sample_missing_coverage_fails_finish() {
+        let mut pop = Population::new(2, 10).unwrap();
+        pop.get(1).unwrap().borrow_mut().ancestry[0].segment.left = 3;
+        let _ = pop.finish(0);
+    }
+
+    #[test]
+    fn test_record_birth_from_parents_must_tile_genome() {
+        let mut pop = Population::new(2, 10).unwrap();
+        for parents in [
+            vec![(0, 0, 5)],
+            vec![(0, 0, 5), (1, 6, 10)],
+            vec![(0, 0, 6), (1, 5, 10)],
+            vec![(0, 0, 5), (2, 5, 10)],
+        ] {
+            assert!(pop.record_birth_from_parents(1, &parents).is_err());
+        }
+        assert!(pop.births.is_empty());
     }
 }
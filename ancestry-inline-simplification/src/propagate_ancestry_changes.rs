@@ -4,13 +4,24 @@ use crate::segments::AncestrySegment;
 use crate::segments::HalfOpenInterval;
 use crate::InlineAncestryError;
 
+/// Counts of the work done by one call
+/// to [`propagate_ancestry_changes`].
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct PropagationStats {
+    /// Number of nodes popped from the heap.
+    pub(crate) popped: usize,
+    /// Number of intervals where more than one child
+    /// lineage overlapped, summed over all processed nodes.
+    pub(crate) coalescences: usize,
+}
+
 pub fn propagate_ancestry_changes(
     genome_length: crate::LargeSignedInteger,
     node_heap: &mut NodeHeap,
-) -> Result<i32, InlineAncestryError> {
-    let mut popped = 0;
+) -> Result<PropagationStats, InlineAncestryError> {
+    let mut stats = PropagationStats::default();
     while let Some(mut n) = node_heap.pop() {
-        popped += 1;
+        stats.popped += 1;
         n.preprocess(genome_length);
         let mut node = Node::from(n);
         #[cfg(debug_assertions)]
@@ -22,7 +33,7 @@ pub fn propagate_ancestry_changes(
             ancestry
         };
 
-        let changed = node.update_ancestry()?;
+        let changed = node.update_ancestry(&mut stats)?;
 
         #[cfg(debug_assertions)]
         {
@@ -92,5 +103,111 @@ pub fn propagate_ancestry_changes(
         }
     }
     assert!(node_heap.is_empty());
-    Ok(popped)
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LargeSignedInteger;
+
+    const L: LargeSignedInteger = 10;
+
+    fn founder(index: i32) -> Node {
+        Node::new_alive_with_ancestry_mapping_to_self(index, 0, L)
+    }
+
+    fn birth(index: i32, birth_time: LargeSignedInteger, parents: &[&Node]) -> Node {
+        let mut child = Node::new_alive_with_ancestry_mapping_to_self(index, birth_time, L);
+        let step = L / parents.len() as LargeSignedInteger;
+        for (i, parent) in parents.iter().enumerate() {
+            let left = i as LargeSignedInteger * step;
+            let right = if i + 1 == parents.len() {
+                L
+            } else {
+                left + step
+            };
+            let mut parent = (*parent).clone();
+            parent
+                .add_child_segment(left, right, child.clone())
+                .unwrap();
+            child.add_parent(parent).unwrap();
+        }
+        child
+    }
+
+    fn propagate(deaths: &[&Node], births: &[&Node]) -> PropagationStats {
+        let mut heap = NodeHeap::default();
+        for &d in deaths {
+            heap.push_death(d.clone()).unwrap();
+        }
+        for &b in births {
+            heap.push_birth(b.clone()).unwrap();
+        }
+        propagate_ancestry_changes(L, &mut heap).unwrap()
+    }
+
+    #[test]
+    fn test_simple_coalescence() {
+        let p = founder(0);
+        let q = founder(1);
+        let a = birth(2, 1, &[&p]);
+        let b = birth(3, 1, &[&p]);
+
+        let stats = propagate(&[&p, &q], &[&a, &b]);
+        assert_eq!(stats.coalescences, 1);
+
+        // p is the MRCA of a and b over the whole genome
+        assert!(!p.is_alive());
+        assert_eq!(p.borrow().ancestry.len(), 1);
+        assert_eq!(p.borrow().ancestry[0].child, p);
+        assert_eq!(p.borrow().children.len(), 2);
+        for child in [&a, &b] {
+            let segs = &p.borrow().children[child];
+            assert_eq!(segs.len(), 1);
+            assert_eq!((segs[0].left, segs[0].right), (0, L));
+            assert!(child.borrow().parents.contains(&p));
+        }
+
+        // q left no descendants
+        assert!(q.borrow().ancestry.is_empty());
+        assert!(q.borrow().children.is_empty());
+    }
+
+    #[test]
+    fn test_unary_passthrough() {
+        let g = founder(0);
+        let c = birth(1, 1, &[&g]);
+
+        let stats = propagate(&[&g], &[&c]);
+        assert_eq!(stats.coalescences, 0);
+
+        // g is unary: its ancestry passes straight through to c
+        // and it is no longer an edge parent.
+        assert_eq!(g.borrow().ancestry.len(), 1);
+        assert_eq!(g.borrow().ancestry[0].child, c);
+        assert!(g.borrow().children.is_empty());
+        assert!(c.borrow().parents.is_empty());
+    }
+
+    #[test]
+    fn test_lost_lineage() {
+        let p = founder(0);
+        let a = birth(1, 1, &[&p]);
+        let b = birth(2, 1, &[&p]);
+        let _ = propagate(&[&p], &[&a, &b]);
+        assert_eq!(p.borrow().children.len(), 2);
+
+        // a dies without offspring, so p is
+        // no longer a coalescence node.
+        let c = birth(3, 2, &[&b]);
+        let stats = propagate(&[&a], &[&c]);
+        assert_eq!(stats.coalescences, 0);
+        assert!(a.borrow().ancestry.is_empty());
+        assert!(a.borrow().parents.is_empty());
+        assert_eq!(p.borrow().ancestry.len(), 1);
+        assert_eq!(p.borrow().ancestry[0].child, b);
+        assert!(p.borrow().children.is_empty());
+        assert!(!crate::util::all_reachable_nodes(&[b, c]).contains(&p));
+    }
 }
@@ -1,6 +1,7 @@
 use crate::node::Node;
 use crate::InlineAncestryError;
 use crate::LargeSignedInteger;
+use crate::SignedInteger;
 use std::cmp::Ordering;
 
 pub(crate) trait HalfOpenInterval {
@@ -38,12 +39,29 @@ impl Segment {
         debug_assert!(left >= 0);
         Self { left, right }
     }
+
+    /// `true` if `left <= position < right`.
+    pub fn contains(&self, position: LargeSignedInteger) -> bool {
+        self.left <= position && position < self.right
+    }
+
+    /// `true` if one segment ends where the other starts.
+    pub fn is_adjacent(&self, other: &Segment) -> bool {
+        self.right == other.left || other.right == self.left
+    }
+
+    /// `true` if the union of the two segments is a single segment,
+    /// meaning that they overlap or are adjacent.
+    pub fn can_merge(&self, other: &Segment) -> bool {
+        self.overlaps(other) || self.is_adjacent(other)
+    }
 }
 
+/// A segment of a node's ancestry and the child it maps to.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct AncestrySegment {
-    pub segment: Segment,
-    pub child: Node,
+    pub(crate) segment: Segment,
+    pub(crate) child: Node,
 }
 
 impl AncestrySegment {
@@ -53,12 +71,40 @@ impl AncestrySegment {
             child,
         }
     }
+
+    /// The index of the child node.
+    pub fn child_id(&self) -> SignedInteger {
+        self.child.borrow().index
+    }
+
+    /// The left end of the segment.
+    pub fn left(&self) -> LargeSignedInteger {
+        self.segment.left
+    }
+
+    /// The right end of the segment.
+    pub fn right(&self) -> LargeSignedInteger {
+        self.segment.right
+    }
+
+    /// `true` if the segments are adjacent and map to the same child.
+    pub fn is_adjacent(&self, other: &AncestrySegment) -> bool {
+        self.child == other.child && self.segment.is_adjacent(&other.segment)
+    }
+
+    /// `true` if the segments map to the same child and
+    /// their union is a single segment.
+    pub fn can_merge(&self, other: &AncestrySegment) -> bool {
+        self.child == other.child && self.segment.can_merge(&other.segment)
+    }
 }
 
+/// An overlap reported by the ancestry overlapper:
+/// a segment of ancestry and the node it maps to.
 #[derive(Clone, Eq, Debug, PartialEq)]
-pub(crate) struct AncestryIntersection {
-    pub ancestry_segment: Segment,
-    pub mapped_node: Node,
+pub struct AncestryIntersection {
+    pub(crate) ancestry_segment: Segment,
+    pub(crate) mapped_node: Node,
 }
 
 impl AncestryIntersection {
@@ -68,6 +114,21 @@ impl AncestryIntersection {
             mapped_node,
         }
     }
+
+    /// The index of the node that the ancestry maps to.
+    pub fn mapped_id(&self) -> SignedInteger {
+        self.mapped_node.borrow().index
+    }
+
+    /// The left end of the segment.
+    pub fn left(&self) -> LargeSignedInteger {
+        self.ancestry_segment.left
+    }
+
+    /// The right end of the segment.
+    pub fn right(&self) -> LargeSignedInteger {
+        self.ancestry_segment.right
+    }
 }
 
 impl HalfOpenInterval for Segment {
@@ -140,4 +201,54 @@ mod tests {
         v.sort();
         assert!(v.windows(2).all(|w| w[0].left() < w[1].left()));
     }
+
+    #[test]
+    fn test_node_id_accessors() {
+        let segment = AncestrySegment::new(0, 3, Node::new_alive(7, 1));
+        assert_eq!(segment.child_id(), 7);
+        assert_eq!((segment.left(), segment.right()), (0, 3));
+        let intersection = AncestryIntersection::new(1, 4, Node::new_alive(11, 1));
+        assert_eq!(intersection.mapped_id(), 11);
+        assert_eq!((intersection.left(), intersection.right()), (1, 4));
+    }
+
+    #[test]
+    fn test_segment_contains() {
+        let s = Segment::new(3, 6).unwrap();
+        assert!(!s.contains(2));
+        assert!(s.contains(3));
+        assert!(s.contains(5));
+        assert!(!s.contains(6));
+    }
+
+    #[test]
+    fn test_segment_adjacency() {
+        let s = Segment::new(3, 6).unwrap();
+        for (other, adjacent, mergeable) in [
+            (Segment::new(0, 3).unwrap(), true, true),
+            (Segment::new(6, 9).unwrap(), true, true),
+            (Segment::new(0, 2).unwrap(), false, false),
+            (Segment::new(7, 9).unwrap(), false, false),
+            (Segment::new(5, 9).unwrap(), false, true),
+            (Segment::new(4, 5).unwrap(), false, true),
+        ] {
+            assert_eq!(s.is_adjacent(&other), adjacent, "{:?}", other);
+            assert_eq!(other.is_adjacent(&s), adjacent, "{:?}", other);
+            assert_eq!(s.can_merge(&other), mergeable, "{:?}", other);
+            assert_eq!(other.can_merge(&s), mergeable, "{:?}", other);
+        }
+    }
+
+    #[test]
+    fn test_ancestry_segment_adjacency_requires_same_child() {
+        let node = Node::new_alive(0, 1);
+        let a = AncestrySegment::new(0, 3, node.clone());
+        let b = AncestrySegment::new(3, 6, node.clone());
+        let c = AncestrySegment::new(3, 6, Node::new_alive(1, 1));
+        assert!(a.is_adjacent(&b));
+        assert!(a.can_merge(&b));
+        assert!(!a.is_adjacent(&c));
+        assert!(!a.can_merge(&c));
+        assert!(a.can_merge(&AncestrySegment::new(2, 4, node)));
+    }
 }
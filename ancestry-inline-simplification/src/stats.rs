@@ -0,0 +1,206 @@
+use crate::population::Population;
+use crate::LargeSignedInteger;
+use crate::SignedInteger;
+use hashbrown::HashMap;
+use neutral_evolution::EvolveAncestry;
+
+impl Population {
+    /// Branch-length diversity of the alive nodes.
+    ///
+    /// For each pair of alive nodes, the total length (in units
+    /// of birth time) of the branches separating them in each
+    /// local tree, weighted by the tree's span and averaged over
+    /// the genome and over pairs.
+    /// A pair whose nodes are in different trees is separated by
+    /// the branches from each node to its root, as in tskit.
+    pub fn branch_diversity(&self) -> f64 {
+        let samples = self.current_sample_ids();
+        if samples.len() < 2 {
+            return 0.0;
+        }
+        let genome_length = self.genome_length();
+
+        let birth_time = self
+            .reachable_nodes_sorted()
+            .iter()
+            .map(|n| {
+                let b = n.borrow();
+                (b.index, b.birth_time)
+            })
+            .collect::<HashMap<SignedInteger, LargeSignedInteger>>();
+
+        let mut edges = vec![];
+        let mut breakpoints = vec![0, genome_length];
+        self.for_each_edge(|parent, child, left, right| {
+            edges.push((parent, child, left, right));
+            breakpoints.push(left);
+            breakpoints.push(right);
+        });
+        breakpoints.sort_unstable();
+        breakpoints.dedup();
+
+        let mut total = 0.0;
+        for interval in breakpoints.windows(2) {
+            let (left, right) = (interval[0], interval[1]);
+            let parent = edges
+                .iter()
+                .filter(|e| e.2 <= left && left < e.3)
+                .map(|e| (e.1, e.0))
+                .collect::<HashMap<SignedInteger, SignedInteger>>();
+            let lineage = |mut u: SignedInteger| {
+                let mut rv = vec![u];
+                while let Some(p) = parent.get(&u) {
+                    rv.push(*p);
+                    u = *p;
+                }
+                rv
+            };
+            let lineages = samples.iter().map(|s| lineage(*s)).collect::<Vec<_>>();
+
+            let mut distance: LargeSignedInteger = 0;
+            for (i, li) in lineages.iter().enumerate() {
+                for lj in lineages.iter().skip(i + 1) {
+                    let ti = birth_time[&li[0]];
+                    let tj = birth_time[&lj[0]];
+                    distance += match li.iter().find(|u| lj.contains(u)) {
+                        Some(mrca) => ti + tj - 2 * birth_time[mrca],
+                        None => {
+                            ti - birth_time[li.last().unwrap()] + tj
+                                - birth_time[lj.last().unwrap()]
+                        }
+                    };
+                }
+            }
+            total += (distance * (right - left)) as f64;
+        }
+
+        let num_pairs = samples.len() * (samples.len() - 1) / 2;
+        total / (genome_length as f64 * num_pairs as f64)
+    }
+
+    /// The largest number of child segments of a single
+    /// reachable node that overlap at any position.
+    ///
+    /// This is the most overlaps the ancestry overlapper
+    /// handles at once when processing a node.
+    pub fn max_overlap_degree(&self) -> usize {
+        let mut rv = 0;
+        let mut events = vec![];
+        for node in self.all_reachable_nodes() {
+            events.clear();
+            for segments in node.borrow().children.values() {
+                for s in segments {
+                    events.push((s.left, 1));
+                    events.push((s.right, -1));
+                }
+            }
+            // Segments are half-open, so ends sort before starts.
+            events.sort_unstable();
+            let mut degree: i64 = 0;
+            for (_, delta) in events.iter() {
+                degree += delta;
+                rv = rv.max(degree as usize);
+            }
+        }
+        rv
+    }
+
+    /// Histogram of the number of distinct children of each
+    /// reachable node, indexed by the number of children.
+    ///
+    /// A node counts a child once however many segments
+    /// it passes to that child.
+    pub fn child_count_distribution(&self) -> Vec<usize> {
+        let mut rv = vec![];
+        for node in self.all_reachable_nodes() {
+            let count = node
+                .borrow()
+                .children
+                .values()
+                .filter(|segments| !segments.is_empty())
+                .count();
+            if count >= rv.len() {
+                rv.resize(count + 1, 0);
+            }
+            rv[count] += 1;
+        }
+        rv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_overlap_degree() {
+        let mut pop = Population::new(4, 10).unwrap();
+        assert_eq!(pop.max_overlap_degree(), 0);
+
+        for i in 0..4 {
+            pop.record_death(i).unwrap();
+        }
+        pop.record_birth_from_parents(1, &[(0, 0, 10)]).unwrap();
+        pop.record_birth_from_parents(1, &[(0, 0, 10)]).unwrap();
+        pop.record_birth_from_parents(1, &[(0, 0, 5), (1, 5, 10)])
+            .unwrap();
+        pop.record_birth_from_parents(1, &[(1, 0, 10)]).unwrap();
+        pop.simplify(1).unwrap();
+
+        // Node 0 has three children on [0, 5).
+        // Node 1 has two children on [5, 10).
+        assert_eq!(pop.max_overlap_degree(), 3);
+    }
+
+    #[test]
+    fn test_child_count_distribution() {
+        let mut pop = Population::new(4, 10).unwrap();
+        assert_eq!(pop.child_count_distribution(), vec![4]);
+
+        for i in 0..4 {
+            pop.record_death(i).unwrap();
+        }
+        pop.record_birth_from_parents(1, &[(0, 0, 10)]).unwrap();
+        pop.record_birth_from_parents(1, &[(0, 0, 3), (0, 7, 10), (1, 3, 7)])
+            .unwrap();
+        pop.record_birth_from_parents(1, &[(0, 0, 5), (1, 5, 10)])
+            .unwrap();
+        pop.record_birth_from_parents(1, &[(1, 0, 10)]).unwrap();
+        pop.simplify(1).unwrap();
+
+        // The four births have no children, node 1 has three,
+        // and node 0 has three despite passing two segments
+        // to one of them.
+        // Nodes 2 and 3 are no longer reachable.
+        assert_eq!(pop.child_count_distribution(), vec![4, 0, 0, 2]);
+    }
+
+    #[test]
+    fn test_branch_diversity() {
+        let mut pop = Population::new(3, 10).unwrap();
+        assert_eq!(pop.branch_diversity(), 0.0);
+
+        for i in 0..3 {
+            pop.record_death(i).unwrap();
+        }
+        pop.record_birth_from_parents(1, &[(0, 0, 10)]).unwrap();
+        pop.record_birth_from_parents(1, &[(0, 0, 10)]).unwrap();
+        pop.record_birth_from_parents(1, &[(1, 0, 5), (0, 5, 10)])
+            .unwrap();
+        pop.simplify(1).unwrap();
+
+        for i in 0..3 {
+            pop.record_death(i).unwrap();
+            pop.record_birth_from_parents(2, &[(i, 0, 10)]).unwrap();
+        }
+        pop.simplify(2).unwrap();
+
+        // On [0, 5), the first two samples coalesce in node 0 and
+        // the third is in a tree of its own: the pairwise distances
+        // are 4, 2, and 2.
+        // On [5, 10), all three coalesce in node 0: the distances
+        // are all 4.
+        let expected = (8.0 * 5.0 + 12.0 * 5.0) / (10.0 * 3.0);
+        assert!((pop.branch_diversity() - expected).abs() < 1e-12);
+    }
+}
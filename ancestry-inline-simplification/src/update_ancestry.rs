@@ -2,6 +2,7 @@ use std::process::Output;
 
 use crate::ancestry_overlapper::AncestryOverlapper;
 use crate::node::{Node, NodeData};
+use crate::propagate_ancestry_changes::PropagationStats;
 use crate::segments::HalfOpenInterval;
 use crate::segments::Segment;
 use crate::segments::{AncestryIntersection, AncestrySegment};
@@ -78,6 +79,7 @@ fn process_overlaps(
     overlapper: &mut AncestryOverlapper,
     output_ancestry: &mut Vec<AncestrySegment>,
     node: &mut Node,
+    stats: &mut PropagationStats,
 ) {
     let mut borrowed_node = node.borrow_mut();
     for (left, right, overlaps) in overlapper {
@@ -92,6 +94,7 @@ fn process_overlaps(
             }
         } else {
             debug_assert!(*node == mapped_node);
+            stats.coalescences += 1;
             for overlap in borrowed_overlaps.iter() {
                 update_child_segments(&mut borrowed_node, &overlap.mapped_node, left, right);
             }
@@ -116,7 +119,7 @@ fn process_overlaps(
 }
 
 #[inline(never)]
-pub(crate) fn update_ancestry(node: &mut Node) -> bool {
+pub(crate) fn update_ancestry(node: &mut Node, stats: &mut PropagationStats) -> bool {
     let self_alive = node.is_alive();
 
     let mut overlapper = make_overlapper(node);
@@ -135,7 +138,7 @@ pub(crate) fn update_ancestry(node: &mut Node) -> bool {
         borrowed_node.children.clear();
     }
 
-    process_overlaps(&mut overlapper, &mut output_ancestry, node);
+    process_overlaps(&mut overlapper, &mut output_ancestry, node, stats);
 
     //if !self_alive {
     //    // Remove trailing input ancestry
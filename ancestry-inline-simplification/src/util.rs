@@ -15,6 +15,50 @@ pub(crate) fn non_overlapping_segments<T: HalfOpenInterval>(
     }
 }
 
+/// Returns the node that `node`'s ancestry maps to at `position`,
+/// or `None` if `position` is not covered by `node`'s ancestry.
+pub(crate) fn ancestry_mapped_at(node: &Node, position: crate::LargeSignedInteger) -> Option<Node> {
+    crate::interval_index::stab(&node.borrow().ancestry, position).map(|a| a.child.clone())
+}
+
+/// Follows the ancestry mapping at `position` from `node` down
+/// to the first node whose ancestry maps to itself, which is
+/// where a mutation on `node` is placed.
+/// Nodes removed from the graph keep the mapping they had
+/// when removed, so this passes through them.
+/// Returns `None` if the ancestry does not cover `position`.
+pub(crate) fn mutation_mapped_node(
+    node: &Node,
+    position: crate::LargeSignedInteger,
+) -> Option<Node> {
+    let mut current = node.clone();
+    loop {
+        let mapped = ancestry_mapped_at(&current, position)?;
+        if mapped == current {
+            return Some(mapped);
+        }
+        // Ancestry maps to strictly younger nodes,
+        // so this terminates.
+        current = mapped;
+    }
+}
+
+/// Returns `true` if `node`'s ancestry tiles `[0, genome_length)`
+/// with no gaps.
+pub(crate) fn validate_covers_genome(
+    node: &Node,
+    genome_length: crate::LargeSignedInteger,
+) -> bool {
+    let mut last_right = 0;
+    for a in node.borrow().ancestry.iter() {
+        if a.left() != last_right {
+            return false;
+        }
+        last_right = a.right();
+    }
+    last_right == genome_length
+}
+
 pub fn all_reachable_nodes(nodes: &[Node]) -> HashSet<Node> {
     let mut reachable = HashSet::new();
 
@@ -42,12 +86,16 @@ pub fn validate_graph(
     for node in &reachable {
         node.non_overlapping_segments()?;
         let borrowed_node = node.borrow();
-        if borrowed_node.is_alive() {
-            assert_eq!(borrowed_node.ancestry.len(), 1);
-            assert!(borrowed_node
-                .ancestry
-                .iter()
-                .all(|a| a.left() == 0 && a.right() == genome_length));
+        if borrowed_node.is_alive()
+            && (borrowed_node.ancestry.len() != 1
+                || borrowed_node
+                    .ancestry
+                    .iter()
+                    .any(|a| a.left() != 0 || a.right() != genome_length))
+        {
+            return Err(InlineAncestryError::InvalidAliveAncestry {
+                index: borrowed_node.index,
+            });
         }
         for child in borrowed_node.children.keys() {
             if !reachable.contains(child) {
@@ -150,3 +150,146 @@ fn test_simulation_round_trip_overlapping_gens() {
         }
     }
 }
+
+#[test]
+fn test_table_conversion_is_reproducible() {
+    for seed in [101, 8512389] {
+        let p = Parameters::new(0.5, 1e-1, 100).unwrap();
+        let mut pop = Population::new(10, 100).unwrap();
+        evolve(seed, p, &mut pop).unwrap();
+        let mut pop2 = Population::new(10, 100).unwrap();
+        evolve(seed, p, &mut pop2).unwrap();
+
+        let tables = pop.to_tables().unwrap();
+        let tables_again = pop.to_tables().unwrap();
+        let tables2 = tskit::TableCollection::try_from(pop2).unwrap();
+        assert!(tables.equals(&tables_again, tskit::TableEqualityOptions::default()));
+        assert!(tables.equals(&tables2, tskit::TableEqualityOptions::default()));
+    }
+}
+
+#[test]
+fn test_for_each_edge_matches_table_conversion() {
+    let p = Parameters::new(0.5, 1e-1, 100).unwrap();
+    let mut pop = Population::new(10, 100).unwrap();
+    evolve(101, p, &mut pop).unwrap();
+
+    // Output node ids follow the sorted order of reachable nodes
+    let ids = pop
+        .reachable_nodes_sorted()
+        .iter()
+        .enumerate()
+        .map(|(i, n)| (n.borrow().index, i as i32))
+        .collect::<std::collections::HashMap<_, _>>();
+    let mut edges = vec![];
+    pop.for_each_edge(|parent, child, left, right| {
+        edges.push((ids[&parent], ids[&child], left, right))
+    });
+    edges.sort_unstable();
+
+    let tables = pop.to_tables().unwrap();
+    let mut table_edges = tables
+        .edges_iter()
+        .map(|e| {
+            (
+                i32::from(e.parent),
+                i32::from(e.child),
+                f64::from(e.left) as i64,
+                f64::from(e.right) as i64,
+            )
+        })
+        .collect::<Vec<_>>();
+    table_edges.sort_unstable();
+    assert!(!edges.is_empty());
+    assert_eq!(edges, table_edges);
+}
+
+#[test]
+fn test_scripted_deaths_match_across_backends() {
+    let nsteps = 50;
+    // Each step, a third of the population dies.
+    let schedule = (1..nsteps + 1)
+        .map(|step| {
+            (0..10)
+                .filter(|i| (i + step) % 3 == 0)
+                .collect::<Vec<usize>>()
+        })
+        .collect::<Vec<_>>();
+    let deaths = neutral_evolution::ScriptedDeath::new(10, schedule.clone()).unwrap();
+    let p = Parameters::new(0.5, 1e-1, nsteps as i64).unwrap();
+
+    let mut pop = Population::new(10, 100).unwrap();
+    let mut tables = EvolvableTableCollection::new(100, 10, 1).unwrap();
+    neutral_evolution::evolve_with_deaths(101, p, deaths.clone(), &mut pop).unwrap();
+    neutral_evolution::evolve_with_deaths(101, p, deaths, &mut tables).unwrap();
+
+    for (i, node) in pop.nodes.iter().enumerate() {
+        let last_death = schedule.iter().rposition(|d| d.contains(&i)).unwrap() + 1;
+        assert_eq!(node.borrow().birth_time, last_death as i64);
+    }
+
+    // The alive node at index i, as an output node id.
+    let samples = tables.alive_nodes().to_vec();
+    let ids = pop
+        .reachable_nodes_sorted()
+        .iter()
+        .enumerate()
+        .map(|(i, n)| (n.borrow().index, NodeId::from(i as i32)))
+        .collect::<std::collections::HashMap<_, _>>();
+    let pop_samples = pop
+        .current_sample_ids()
+        .iter()
+        .map(|i| ids[i])
+        .collect::<Vec<_>>();
+
+    let tables = tskit::TableCollection::from(tables);
+    let pop_tables = pop.to_tables().unwrap();
+    assert_eq!(pop_tables.nodes().num_rows(), tables.nodes().num_rows());
+    let tmrca = pairwise_tmrca(&tables, &samples);
+    assert!(tmrca.len() > 1);
+    assert_eq!(tmrca, pairwise_tmrca(&pop_tables, &pop_samples));
+}
+
+// For each tree, the time to the MRCA of each pair of `samples`.
+// This does not depend on how either backend labels the
+// ancestral nodes.
+fn pairwise_tmrca(
+    tables: &tskit::TableCollection,
+    samples: &[NodeId],
+) -> Vec<((f64, f64), Vec<f64>)> {
+    // Simplification gives samples[i] the output id i.
+    let mut tables = tables.deepcopy().unwrap();
+    tables
+        .simplify(samples, tskit::SimplificationOptions::default(), false)
+        .unwrap();
+    let ts = tables
+        .tree_sequence(tskit::TreeSequenceFlags::BUILD_INDEXES)
+        .unwrap();
+    let tables = ts.dump_tables().unwrap();
+    let nodes = tables.nodes();
+    let mut rv = vec![];
+    let mut tree_iter = ts.tree_iterator(tskit::TreeFlags::default()).unwrap();
+    while let Some(tree) = tree_iter.next() {
+        let ancestors = (0..samples.len())
+            .map(|s| {
+                let s = NodeId::from(s as i32);
+                let mut a = vec![s];
+                a.extend(tree.parents(s).unwrap());
+                a
+            })
+            .collect::<Vec<_>>();
+        let mut tmrca = vec![];
+        for i in 0..samples.len() {
+            for j in i + 1..samples.len() {
+                let t = ancestors[i]
+                    .iter()
+                    .find(|a| ancestors[j].contains(a))
+                    .map_or(f64::INFINITY, |a| f64::from(nodes.time(*a).unwrap()));
+                tmrca.push(t);
+            }
+        }
+        let (left, right) = tree.interval();
+        rv.push(((f64::from(left), f64::from(right)), tmrca));
+    }
+    rv
+}
@@ -7,12 +7,38 @@ use std::ops::DerefMut;
 use std::rc::Rc;
 use thiserror::Error;
 
+mod timed;
+
+pub use timed::{Timed, TimingReport};
+
 #[derive(Error, Debug)]
 pub enum ParameterError {
     #[error("{0:?}")]
     BadParameter(String),
 }
 
+#[derive(Error, Debug, Eq, PartialEq)]
+pub enum TransmissionError {
+    #[error("zero-length segment at {position:?}")]
+    ZeroLengthSegment { position: LargeSignedInteger },
+    #[error("invalid segment: [{left:?}, {right:?})")]
+    InvalidSegment {
+        left: LargeSignedInteger,
+        right: LargeSignedInteger,
+    },
+    #[error("segment [{left:?}, {right:?}) is not within [0, {genome_length:?})")]
+    SegmentOutOfBounds {
+        left: LargeSignedInteger,
+        right: LargeSignedInteger,
+        genome_length: LargeSignedInteger,
+    },
+    #[error("segment starting at {left:?} is not after the previous segment, which ends at {previous_right:?}")]
+    UnsortedSegments {
+        left: LargeSignedInteger,
+        previous_right: LargeSignedInteger,
+    },
+}
+
 pub trait EvolveAncestry {
     fn genome_length(&self) -> LargeSignedInteger;
 
@@ -43,23 +69,93 @@ pub trait EvolveAncestry {
 
 type Rng = Rc<RefCell<rand_pcg::Pcg64>>;
 
+/// Decides which individuals die.
+///
+/// [`EvolveAncestry::generate_deaths`] calls [`Death::dies`]
+/// once per individual, in order, at each time step.
 pub struct Death {
-    rng: Rng,
-    death_probability: f64,
-    uniform: rand::distributions::Uniform<f64>,
+    source: DeathSource,
+}
+
+enum DeathSource {
+    Random {
+        rng: Rng,
+        death_probability: f64,
+        uniform: rand::distributions::Uniform<f64>,
+    },
+    Scripted(ScriptedDeath),
 }
 
 impl Death {
     fn new(death_probability: f64, rng: Rng) -> Self {
         Self {
-            rng,
-            death_probability,
-            uniform: rand::distributions::Uniform::new(0., 1.),
+            source: DeathSource::Random {
+                rng,
+                death_probability,
+                uniform: rand::distributions::Uniform::new(0., 1.),
+            },
         }
     }
 
     pub fn dies(&mut self) -> bool {
-        self.uniform.sample(&mut self.rng.borrow_mut().deref_mut()) <= self.death_probability
+        match &mut self.source {
+            DeathSource::Random {
+                rng,
+                death_probability,
+                uniform,
+            } => uniform.sample(&mut rng.borrow_mut().deref_mut()) <= *death_probability,
+            DeathSource::Scripted(scripted) => scripted.dies(),
+        }
+    }
+}
+
+impl From<ScriptedDeath> for Death {
+    fn from(scripted: ScriptedDeath) -> Self {
+        Self {
+            source: DeathSource::Scripted(scripted),
+        }
+    }
+}
+
+/// A fixed schedule of deaths, for deterministic tests.
+///
+/// `schedule[i]` holds the indexes of the individuals that die
+/// at time step `i + 1`.
+/// Nobody dies after the end of the schedule.
+/// See [`evolve_with_deaths`].
+#[derive(Clone, Debug)]
+pub struct ScriptedDeath {
+    popsize: usize,
+    schedule: Vec<Vec<usize>>,
+    num_calls: usize,
+}
+
+impl ScriptedDeath {
+    pub fn new(popsize: usize, schedule: Vec<Vec<usize>>) -> Result<Self, ParameterError> {
+        if popsize == 0 {
+            return Err(ParameterError::BadParameter(
+                "popsize must be > 0".to_string(),
+            ));
+        }
+        if schedule.iter().flatten().any(|i| *i >= popsize) {
+            return Err(ParameterError::BadParameter(
+                "scheduled deaths must be < popsize".to_string(),
+            ));
+        }
+        Ok(Self {
+            popsize,
+            schedule,
+            num_calls: 0,
+        })
+    }
+
+    fn dies(&mut self) -> bool {
+        let step = self.num_calls / self.popsize;
+        let individual = self.num_calls % self.popsize;
+        self.num_calls += 1;
+        self.schedule
+            .get(step)
+            .is_some_and(|deaths| deaths.contains(&individual))
     }
 }
 
@@ -78,6 +174,70 @@ impl TransmittedSegment {
             parent,
         }
     }
+
+    /// Check that `left < right`.
+    ///
+    /// Implementations of [`EvolveAncestry::record_birth`]
+    /// should call this for each segment before recording anything,
+    /// so that all backends reject the same input.
+    pub fn validate(&self) -> Result<(), TransmissionError> {
+        if self.left == self.right {
+            Err(TransmissionError::ZeroLengthSegment {
+                position: self.left,
+            })
+        } else if self.left > self.right {
+            Err(TransmissionError::InvalidSegment {
+                left: self.left,
+                right: self.right,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Fill any gaps in `breakpoints` so that the result covers
+/// `[0, genome_length)`, assigning each gap to `default_parent`.
+///
+/// Fails if any segment is invalid or not within
+/// `[0, genome_length)`, or if `breakpoints` are not sorted
+/// by `left` and non-overlapping.
+pub fn complete_coverage(
+    breakpoints: &[TransmittedSegment],
+    genome_length: LargeSignedInteger,
+    default_parent: usize,
+) -> Result<Vec<TransmittedSegment>, TransmissionError> {
+    let mut rv = vec![];
+    let mut last_right = 0;
+    for b in breakpoints {
+        b.validate()?;
+        if b.left < 0 || b.right > genome_length {
+            return Err(TransmissionError::SegmentOutOfBounds {
+                left: b.left,
+                right: b.right,
+                genome_length,
+            });
+        }
+        if b.left < last_right {
+            return Err(TransmissionError::UnsortedSegments {
+                left: b.left,
+                previous_right: last_right,
+            });
+        }
+        if b.left > last_right {
+            rv.push(TransmittedSegment::new(last_right, b.left, default_parent));
+        }
+        rv.push(TransmittedSegment::new(b.left, b.right, b.parent));
+        last_right = b.right;
+    }
+    if last_right < genome_length {
+        rv.push(TransmittedSegment::new(
+            last_right,
+            genome_length,
+            default_parent,
+        ));
+    }
+    Ok(rv)
 }
 
 #[derive(Copy, Clone)]
@@ -183,11 +343,40 @@ pub fn evolve<N: EvolveAncestry>(
     seed: u64,
     parameters: Parameters,
     population: &mut N,
+) -> Result<(), Box<dyn Error>> {
+    evolve_details(seed, parameters, None, population)
+}
+
+/// As [`evolve`], but deaths follow `deaths` rather than
+/// the death probability.
+/// Parents and crossovers are still random.
+pub fn evolve_with_deaths<N: EvolveAncestry>(
+    seed: u64,
+    parameters: Parameters,
+    deaths: ScriptedDeath,
+    population: &mut N,
+) -> Result<(), Box<dyn Error>> {
+    if deaths.popsize != population.current_population_size() {
+        return Err(Box::new(ParameterError::BadParameter(
+            "scripted deaths are for a different popsize".to_string(),
+        )));
+    }
+    evolve_details(seed, parameters, Some(deaths), population)
+}
+
+fn evolve_details<N: EvolveAncestry>(
+    seed: u64,
+    parameters: Parameters,
+    deaths: Option<ScriptedDeath>,
+    population: &mut N,
 ) -> Result<(), Box<dyn Error>> {
     let rng = Rc::new(RefCell::<rand_pcg::Pcg64>::new(
         rand_pcg::Pcg64::seed_from_u64(seed),
     ));
-    let mut death = Death::new(parameters.death_probability, rng.clone());
+    let mut death = match deaths {
+        Some(deaths) => Death::from(deaths),
+        None => Death::new(parameters.death_probability, rng.clone()),
+    };
 
     population.setup(parameters.nsteps);
 
@@ -252,6 +441,20 @@ mod tests {
         };
     }
 
+    #[test]
+    fn test_scripted_death() {
+        assert!(ScriptedDeath::new(0, vec![]).is_err());
+        assert!(ScriptedDeath::new(3, vec![vec![3]]).is_err());
+
+        let mut death =
+            Death::from(ScriptedDeath::new(3, vec![vec![0, 2], vec![], vec![1]]).unwrap());
+        let deaths = (0..12).map(|_| death.dies()).collect::<Vec<_>>();
+        assert_eq!(
+            deaths,
+            [true, false, true, false, false, false, false, true, false, false, false, false]
+        );
+    }
+
     #[test]
     fn test_generate_crossover_positions() {
         let mut rng = make_rng(101);
@@ -339,4 +542,66 @@ mod tests {
             validate_transmissions!(expected, transmissions);
         }
     }
+
+    #[test]
+    fn test_complete_coverage() {
+        let genome_length = 10_i64;
+        let default_parent = 2;
+        let breakpoints = vec![make_transmission(2, 4, 0), make_transmission(6, 8, 1)];
+        let expected = vec![
+            make_transmission(0, 2, default_parent),
+            make_transmission(2, 4, 0),
+            make_transmission(4, 6, default_parent),
+            make_transmission(6, 8, 1),
+            make_transmission(8, genome_length, default_parent),
+        ];
+        let completed = complete_coverage(&breakpoints, genome_length, default_parent).unwrap();
+        validate_transmissions!(expected, completed);
+
+        // Full coverage is returned as is
+        let completed = complete_coverage(&expected, genome_length, 3).unwrap();
+        validate_transmissions!(expected, completed);
+
+        let expected = [make_transmission(0, genome_length, default_parent)];
+        let completed = complete_coverage(&[], genome_length, default_parent).unwrap();
+        validate_transmissions!(expected, completed);
+
+        assert_eq!(
+            complete_coverage(&[make_transmission(2, 11, 0)], genome_length, 0).unwrap_err(),
+            TransmissionError::SegmentOutOfBounds {
+                left: 2,
+                right: 11,
+                genome_length
+            }
+        );
+        assert_eq!(
+            complete_coverage(
+                &[make_transmission(4, 6, 0), make_transmission(2, 5, 1)],
+                genome_length,
+                0
+            )
+            .unwrap_err(),
+            TransmissionError::UnsortedSegments {
+                left: 2,
+                previous_right: 6
+            }
+        );
+        assert_eq!(
+            complete_coverage(&[make_transmission(4, 4, 0)], genome_length, 0).unwrap_err(),
+            TransmissionError::ZeroLengthSegment { position: 4 }
+        );
+    }
+
+    #[test]
+    fn test_validate_transmitted_segment() {
+        assert!(make_transmission(0, 1, 0).validate().is_ok());
+        assert_eq!(
+            make_transmission(3, 3, 0).validate(),
+            Err(TransmissionError::ZeroLengthSegment { position: 3 })
+        );
+        assert_eq!(
+            make_transmission(4, 3, 0).validate(),
+            Err(TransmissionError::InvalidSegment { left: 4, right: 3 })
+        );
+    }
 }
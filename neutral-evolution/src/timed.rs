@@ -0,0 +1,94 @@
+use crate::{Death, EvolveAncestry, LargeSignedInteger, TransmittedSegment};
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+/// Time spent in each [`EvolveAncestry`] method
+/// of a [`Timed`] type.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TimingReport {
+    pub generate_deaths: Duration,
+    pub record_birth: Duration,
+    pub simplify: Duration,
+}
+
+impl std::fmt::Display for TimingReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "generate_deaths: {:?}, record_birth: {:?}, simplify: {:?}",
+            self.generate_deaths, self.record_birth, self.simplify
+        )
+    }
+}
+
+/// Wraps any [`EvolveAncestry`] type, delegating to it
+/// while accumulating the time spent in each method.
+pub struct Timed<E: EvolveAncestry> {
+    inner: E,
+    report: TimingReport,
+}
+
+impl<E: EvolveAncestry> Timed<E> {
+    pub fn new(inner: E) -> Self {
+        Self {
+            inner,
+            report: TimingReport::default(),
+        }
+    }
+
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+
+    pub fn report(&self) -> TimingReport {
+        self.report
+    }
+}
+
+impl<E: EvolveAncestry> EvolveAncestry for Timed<E> {
+    fn genome_length(&self) -> LargeSignedInteger {
+        self.inner.genome_length()
+    }
+
+    fn setup(&mut self, final_time: LargeSignedInteger) {
+        self.inner.setup(final_time)
+    }
+
+    fn generate_deaths(&mut self, death: &mut Death) -> usize {
+        let start = Instant::now();
+        let rv = self.inner.generate_deaths(death);
+        self.report.generate_deaths += start.elapsed();
+        rv
+    }
+
+    fn current_population_size(&self) -> usize {
+        self.inner.current_population_size()
+    }
+
+    fn record_birth(
+        &mut self,
+        birth_time: LargeSignedInteger,
+        final_time: LargeSignedInteger,
+        breakpoints: &[TransmittedSegment],
+    ) -> Result<(), Box<dyn Error>> {
+        let start = Instant::now();
+        let rv = self.inner.record_birth(birth_time, final_time, breakpoints);
+        self.report.record_birth += start.elapsed();
+        rv
+    }
+
+    fn simplify(&mut self, current_time_point: LargeSignedInteger) -> Result<(), Box<dyn Error>> {
+        let start = Instant::now();
+        let rv = self.inner.simplify(current_time_point);
+        self.report.simplify += start.elapsed();
+        rv
+    }
+
+    fn finish(&mut self, current_time_point: LargeSignedInteger) -> Result<(), Box<dyn Error>> {
+        self.inner.finish(current_time_point)
+    }
+}
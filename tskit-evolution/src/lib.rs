@@ -1,8 +1,36 @@
-use ancestry_common::{LargeSignedInteger, SignedInteger};
+use ancestry_common::{EncodedNodeMetadata, LargeSignedInteger, SignedInteger};
 use neutral_evolution::EvolveAncestry;
 use tskit::prelude::*;
 use tskit::TableCollection;
 
+/// How new edges are put into sorted order
+/// before simplification.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortingMode {
+    /// Add edges to the edge table as births happen and
+    /// sort all tables before simplifying.
+    #[default]
+    FullSort,
+    /// Buffer the edges of each birth and merge them,
+    /// in sorted order, into the (already sorted) edge table
+    /// before simplifying.
+    /// Gives the same trees as `FullSort`, although
+    /// ancestral node ids may differ.
+    EdgeBuffer,
+}
+
+/// Node flag marking census nodes.
+/// See [`EvolvableTableCollection::add_census`].
+pub const CENSUS_NODE: tskit::RawFlags = 1 << 16;
+
+#[derive(Clone, Copy, Debug)]
+struct BufferedEdge {
+    left: Position,
+    right: Position,
+    parent: NodeId,
+    child: NodeId,
+}
+
 pub struct EvolvableTableCollection {
     tables: TableCollection,
     alive_nodes: Vec<NodeId>,
@@ -11,8 +39,31 @@ pub struct EvolvableTableCollection {
     replacements: Vec<usize>,
     births: Vec<NodeId>,
     bookmark: tskit::types::Bookmark,
+    // If true, the next simplification sorts all edges.
+    bookmark_invalidated: bool,
+    num_edges_after_simplify: usize,
+    // (added, removed) by the last simplification.
+    last_edge_diff: (usize, usize),
     simplification_interval: LargeSignedInteger,
+    // The time point of the most recent call to simplify,
+    // or 0 before the first step.
+    current_time_point: LargeSignedInteger,
     last_time_simplified: Option<LargeSignedInteger>, // TODO: do we really need this?
+    sorting_mode: SortingMode,
+    edge_buffer: Vec<BufferedEdge>,
+    current_population: PopulationId,
+    // Kept as samples by every simplification,
+    // in addition to the alive nodes.
+    preserved_nodes: Vec<NodeId>,
+    persistent_samples: Vec<NodeId>,
+    has_node_metadata_schema: bool,
+    founder_metadata: Vec<EncodedNodeMetadata>,
+    founder_time: Option<LargeSignedInteger>,
+    founders_are_samples: bool,
+    // Keyed on position.
+    site_ids: std::collections::HashMap<LargeSignedInteger, SiteId>,
+    track_coalescence_fraction: bool,
+    coalescence_fraction_history: Vec<(LargeSignedInteger, f64)>,
 }
 
 impl EvolvableTableCollection {
@@ -21,6 +72,11 @@ impl EvolvableTableCollection {
         popsize: SignedInteger,
         simplification_interval: LargeSignedInteger,
     ) -> Result<Self, Box<dyn std::error::Error>> {
+        if simplification_interval < 1 {
+            return Err(Box::new(neutral_evolution::ParameterError::BadParameter(
+                "simplification_interval must be >= 1".to_string(),
+            )));
+        }
         let mut tables = TableCollection::new(tskit::Position::from(sequence_length as f64))?;
         let mut alive_nodes = vec![];
 
@@ -32,11 +88,144 @@ impl EvolvableTableCollection {
             replacements: vec![],
             births: vec![],
             bookmark: tskit::types::Bookmark::new(),
+            bookmark_invalidated: false,
+            num_edges_after_simplify: 0,
+            last_edge_diff: (0, 0),
             simplification_interval,
+            current_time_point: 0,
             last_time_simplified: None,
+            sorting_mode: SortingMode::default(),
+            edge_buffer: vec![],
+            current_population: PopulationId::NULL,
+            preserved_nodes: vec![],
+            persistent_samples: vec![],
+            has_node_metadata_schema: false,
+            founder_metadata: vec![],
+            founder_time: None,
+            founders_are_samples: false,
+            site_ids: std::collections::HashMap::default(),
+            track_coalescence_fraction: false,
+            coalescence_fraction_history: vec![],
         })
     }
 
+    /// Set how edges are sorted prior to simplification.
+    ///
+    /// # Panics
+    ///
+    /// If there are buffered edges that have not
+    /// yet been simplified.
+    pub fn set_sorting_mode(&mut self, mode: SortingMode) {
+        assert!(self.edge_buffer.is_empty());
+        self.sorting_mode = mode;
+    }
+
+    /// Add a deme to the population table.
+    pub fn add_population<M: tskit::metadata::PopulationMetadata>(
+        &mut self,
+        metadata: &M,
+    ) -> Result<PopulationId, tskit::TskitError> {
+        self.tables.add_population_with_metadata(metadata)
+    }
+
+    /// Set the deme assigned to nodes added from now on.
+    /// The default is [`PopulationId::NULL`].
+    pub fn set_current_population(&mut self, population: PopulationId) {
+        self.current_population = population;
+    }
+
+    // Merge the buffered edges into the edge table.
+    // The existing edges are valid input to simplification,
+    // having been output by the previous round, so we only need
+    // to sort the buffer and then interleave the two by parent.
+    // Simplification requires edges sorted by parent time,
+    // with each parent's edges contiguous and sorted by child
+    // and then left.
+    fn flush_edge_buffer(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let nodes = self.tables.nodes();
+        let time = |n: NodeId| f64::from(nodes.time(n).unwrap());
+        let by_child = |a: &BufferedEdge, b: &BufferedEdge| {
+            a.child
+                .cmp(&b.child)
+                .then(f64::from(a.left).partial_cmp(&f64::from(b.left)).unwrap())
+        };
+        self.edge_buffer.sort_by(|a, b| {
+            time(a.parent)
+                .partial_cmp(&time(b.parent))
+                .unwrap()
+                .then(a.parent.cmp(&b.parent))
+                .then(by_child(a, b))
+        });
+
+        let existing = self
+            .tables
+            .edges_iter()
+            .map(|e| BufferedEdge {
+                left: e.left,
+                right: e.right,
+                parent: e.parent,
+                child: e.child,
+            })
+            .collect::<Vec<_>>();
+        let buffer = &self.edge_buffer;
+        let run_end = |edges: &[BufferedEdge], start: usize| {
+            start
+                + edges[start..]
+                    .iter()
+                    .take_while(|e| e.parent == edges[start].parent)
+                    .count()
+        };
+
+        // With ties in parent time, a buffered parent
+        // that is also an existing parent must wait to be
+        // merged with the existing edges.
+        let existing_parents = existing
+            .iter()
+            .map(|e| e.parent)
+            .collect::<std::collections::HashSet<_>>();
+
+        let mut merged = Vec::with_capacity(existing.len() + buffer.len());
+        let (mut i, mut j) = (0, 0);
+        while i < existing.len() && j < buffer.len() {
+            let ei = run_end(&existing, i);
+            if existing[i].parent == buffer[j].parent {
+                let bj = run_end(buffer, j);
+                let start = merged.len();
+                merged.extend_from_slice(&existing[i..ei]);
+                merged.extend_from_slice(&buffer[j..bj]);
+                merged[start..].sort_by(by_child);
+                i = ei;
+                j = bj;
+            } else if time(existing[i].parent) < time(buffer[j].parent)
+                || (time(existing[i].parent) == time(buffer[j].parent)
+                    && existing_parents.contains(&buffer[j].parent))
+            {
+                merged.extend_from_slice(&existing[i..ei]);
+                i = ei;
+            } else {
+                let bj = run_end(buffer, j);
+                merged.extend_from_slice(&buffer[j..bj]);
+                j = bj;
+            }
+        }
+        merged.extend_from_slice(&existing[i..]);
+        merged.extend_from_slice(&buffer[j..]);
+        self.edge_buffer.clear();
+
+        // SAFETY: the tskit::TableCollection does not
+        // allow the managed pointer to be NULL
+        let rv = unsafe {
+            tskit::bindings::tsk_edge_table_clear(&mut (*self.tables.as_mut_ptr()).edges)
+        };
+        if rv < 0 {
+            return Err(Box::new(tskit::TskitError::ErrorCode { code: rv }));
+        }
+        for e in merged {
+            self.tables.add_edge(e.left, e.right, e.parent, e.child)?;
+        }
+        Ok(())
+    }
+
     fn enact_replacements(&mut self) {
         if !self.births.is_empty() {
             assert_eq!(self.replacements.len(), self.births.len());
@@ -48,75 +237,682 @@ impl EvolvableTableCollection {
         self.births.clear();
     }
 
-    fn simplify_details(
+    /// Set the metadata schema of the node table.
+    ///
+    /// Must be called before [`EvolvableTableCollection::set_founder_metadata`].
+    pub fn set_node_metadata_schema(&mut self, schema: &str) -> Result<(), tskit::TskitError> {
+        // SAFETY: the tskit::TableCollection does not
+        // allow the managed pointer to be NULL
+        let rv = unsafe {
+            tskit::bindings::tsk_node_table_set_metadata_schema(
+                &mut (*self.tables.as_mut_ptr()).nodes,
+                schema.as_ptr() as *const std::os::raw::c_char,
+                schema.len() as tskit::bindings::tsk_size_t,
+            )
+        };
+        if rv < 0 {
+            return Err(tskit::TskitError::ErrorCode { code: rv });
+        }
+        self.has_node_metadata_schema = true;
+        Ok(())
+    }
+
+    /// Give each founder node metadata, in the order
+    /// the founders are created by `setup`.
+    ///
+    /// Fails if no node metadata schema has been set, if
+    /// the length of `metadata` is not the population size,
+    /// or if the founders already exist.
+    pub fn set_founder_metadata<M: tskit::metadata::NodeMetadata>(
+        &mut self,
+        metadata: &[M],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.has_node_metadata_schema {
+            return Err("the node metadata schema must be set first".into());
+        }
+        if metadata.len() != self.current_population_size() {
+            return Err("need metadata for each founder".into());
+        }
+        if !self.alive_nodes.is_empty() {
+            return Err("founders have already been created".into());
+        }
+        self.founder_metadata = metadata
+            .iter()
+            .map(EncodedNodeMetadata::new)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(())
+    }
+
+    /// Set the node time of the founders created by `setup`.
+    /// The default is the `final_time` passed to `setup`.
+    ///
+    /// Fails if the founders already exist.
+    ///
+    /// # Panics
+    ///
+    /// `setup` panics if `time` is less than its `final_time`,
+    /// as the founders would not be older than the first births.
+    pub fn set_founder_time(
+        &mut self,
+        time: LargeSignedInteger,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.alive_nodes.is_empty() {
+            return Err("founders have already been created".into());
+        }
+        self.founder_time = Some(time);
+        Ok(())
+    }
+
+    /// If `true`, `setup` flags the founders as samples and
+    /// adds them to the preserved nodes, so that they remain
+    /// samples through simplification, mid-run conversion,
+    /// and `finish`.
+    ///
+    /// Fails if the founders already exist.
+    pub fn set_founders_are_samples(
+        &mut self,
+        founders_are_samples: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.alive_nodes.is_empty() {
+            return Err("founders have already been created".into());
+        }
+        self.founders_are_samples = founders_are_samples;
+        Ok(())
+    }
+
+    /// Add a mutation to `derived_state` at `position` on `node`,
+    /// dated to the node's time.
+    ///
+    /// Mutations at the same position share a site (finite sites),
+    /// and stack: each simplification, and `finish`, sets the parent
+    /// of each mutation to the mutation above it at its site.
+    pub fn add_mutation(
+        &mut self,
+        node: NodeId,
+        position: LargeSignedInteger,
+        derived_state: &[u8],
+    ) -> Result<MutationId, Box<dyn std::error::Error>> {
+        let time = self.tables.nodes().time(node)?;
+        let site = match self.site_ids.get(&position) {
+            Some(site) => *site,
+            None => {
+                let site = self.tables.add_site(position as f64, None)?;
+                self.site_ids.insert(position, site);
+                site
+            }
+        };
+        Ok(self
+            .tables
+            .add_mutation(site, node, MutationId::NULL, time, Some(derived_state))?)
+    }
+
+    // Sort the sites and mutations, leaving the edges as they are.
+    fn sort_sites_and_mutations(&mut self) -> Result<(), tskit::TskitError> {
+        let mut start = tskit::types::Bookmark::new();
+        start.offsets.edges = u64::from(self.tables.edges().num_rows());
+        self.tables
+            .sort(&start, tskit::TableSortOptions::default())?;
+        Ok(())
+    }
+
+    // Refresh the site ids, which sorting may change.
+    fn refresh_site_ids(&mut self) {
+        self.site_ids = self
+            .tables
+            .sites_iter()
+            .map(|s| (f64::from(s.position) as LargeSignedInteger, s.id))
+            .collect();
+    }
+
+    // Refresh the site ids and set the mutation parents.
+    // Requires sorted tables.
+    fn compute_mutation_parents(&mut self) -> Result<(), tskit::TskitError> {
+        self.refresh_site_ids();
+        if self.tables.mutations().num_rows() == 0 {
+            return Ok(());
+        }
+        self.tables.build_index()?;
+        // SAFETY: the tskit::TableCollection does not
+        // allow the managed pointer to be NULL
+        let rv = unsafe {
+            tskit::bindings::tsk_table_collection_compute_mutation_parents(
+                self.tables.as_mut_ptr(),
+                0,
+            )
+        };
+        if rv < 0 {
+            return Err(tskit::TskitError::ErrorCode { code: rv });
+        }
+        Ok(())
+    }
+
+    /// Remember the current alive nodes as census nodes.
+    ///
+    /// Census nodes are flagged with [`CENSUS_NODE`] and are
+    /// kept as samples by all later simplifications, so that
+    /// they appear in the final tree sequence.
+    /// Call this between time steps, after simplification.
+    /// `time` must be the time point passed to that simplification,
+    /// or 0 before the first step, as only the current alive nodes
+    /// can be recorded.
+    ///
+    /// The returned ids are valid until the next simplification.
+    /// See [`EvolvableTableCollection::preserved_nodes`].
+    pub fn add_census(
+        &mut self,
+        time: LargeSignedInteger,
+    ) -> Result<Vec<NodeId>, Box<dyn std::error::Error>> {
+        if time != self.current_time_point {
+            return Err(Box::new(neutral_evolution::ParameterError::BadParameter(
+                format!(
+                    "census time {} is not the current time {}",
+                    time, self.current_time_point
+                ),
+            )));
+        }
+        let mut nodes = self.tables.nodes();
+        let flags = nodes.flags_array_mut();
+        for a in self.alive_nodes.iter() {
+            let f = &mut flags[usize::from(*a)];
+            *f = tskit::NodeFlags::from(f.bits() | CENSUS_NODE);
+            if !self.preserved_nodes.contains(a) {
+                self.preserved_nodes.push(*a);
+            }
+        }
+        Ok(self.alive_nodes.clone())
+    }
+
+    /// The current ids of the nodes kept as samples
+    /// in addition to the alive nodes.
+    pub fn preserved_nodes(&self) -> &[NodeId] {
+        &self.preserved_nodes
+    }
+
+    /// Set nodes to keep as samples by every later simplification,
+    /// replacing any previously set.
+    ///
+    /// The ids are remapped by each simplification.
+    /// See [`EvolvableTableCollection::persistent_samples`].
+    ///
+    /// # Panics
+    ///
+    /// If any node is not in the node table.
+    pub fn set_persistent_samples(&mut self, nodes: &[NodeId]) {
+        let num_nodes = usize::try_from(self.tables.nodes().num_rows()).unwrap();
+        assert!(nodes
+            .iter()
+            .all(|n| !n.is_null() && usize::from(*n) < num_nodes));
+        self.persistent_samples = nodes.to_vec();
+    }
+
+    /// The current ids of the persistent samples.
+    pub fn persistent_samples(&self) -> &[NodeId] {
+        &self.persistent_samples
+    }
+
+    /// Simplify the tables regardless of the simplification interval.
+    ///
+    /// Useful in custom step loops or before exporting intermediate tables.
+    ///
+    /// If sorting or simplification fails, the buffered edges are
+    /// left in the edge table and the next simplification sorts all
+    /// of the tables.
+    /// Use [`EvolvableTableCollection::simplify_now_checked`] to
+    /// restore the tables instead.
+    pub fn simplify_now(
+        &mut self,
+        current_time_point: LargeSignedInteger,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.simplify_details(current_time_point, true)
+    }
+
+    /// Like [`EvolvableTableCollection::simplify_now`], but if
+    /// sorting or simplification fails, the tables are restored
+    /// to their state before the call.
+    ///
+    /// This copies the tables, so prefer `simplify_now` unless
+    /// the collection must remain usable after a failure.
+    /// Invalid sample ids are always reported before anything
+    /// changes.
+    pub fn simplify_now_checked(
         &mut self,
         current_time_point: LargeSignedInteger,
-        force: bool,
     ) -> Result<(), Box<dyn std::error::Error>> {
         self.enact_replacements();
-        if current_time_point > 0
-            && (force || current_time_point % self.simplification_interval == 0)
-        {
-            self.tables.full_sort(tskit::TableSortOptions::default())?;
+        let tables = self.tables.deepcopy()?;
+        let edge_buffer = self.edge_buffer.clone();
+        let site_ids = self.site_ids.clone();
+        let bookmark_invalidated = self.bookmark_invalidated;
+        match self.simplify_details(current_time_point, true) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                self.tables = tables;
+                self.edge_buffer = edge_buffer;
+                self.site_ids = site_ids;
+                self.bookmark_invalidated = bookmark_invalidated;
+                Err(e)
+            }
+        }
+    }
+
+    /// Make the next simplification sort all of the tables,
+    /// after which sorting is incremental again.
+    ///
+    /// Call this after modifying the edge table other than by
+    /// recording births, which may leave the existing edges
+    /// unsorted.
+    /// Only affects [`SortingMode::EdgeBuffer`]: otherwise,
+    /// every simplification sorts all of the tables.
+    pub fn invalidate_bookmark(&mut self) {
+        self.bookmark = tskit::types::Bookmark::new();
+        self.bookmark_invalidated = true;
+    }
+
+    /// The sequence length as an integer.
+    ///
+    /// Fails if the sequence length has a fractional part,
+    /// which [`EvolveAncestry::genome_length`] would truncate.
+    pub fn try_genome_length(&self) -> Result<LargeSignedInteger, Box<dyn std::error::Error>> {
+        let length = f64::from(self.tables.sequence_length());
+        if length.fract() != 0.0 {
+            return Err(format!("sequence length {} is not an integer", length).into());
+        }
+        Ok(length as LargeSignedInteger)
+    }
+
+    /// `(added, removed)`: the net change in the number of edges
+    /// from the end of the second-to-last simplification to the
+    /// end of the last one.
+    ///
+    /// `added` counts the edges recorded by births in between,
+    /// plus any net increase due to simplification splitting edges.
+    /// `removed` counts any net decrease due to simplification.
+    pub fn edge_diff_since_last_simplify(&self) -> (usize, usize) {
+        self.last_edge_diff
+    }
+
+    /// The ids of the alive nodes.
+    ///
+    /// Simplification gives the samples the smallest output ids,
+    /// in order, so after each simplification the alive node at
+    /// index `i` has id `i`.
+    /// Nothing else depends on iteration order, so the same
+    /// inputs always give identical tables.
+    pub fn alive_nodes(&self) -> &[NodeId] {
+        &self.alive_nodes
+    }
+
+    /// If `track` is `true`, each simplification records the
+    /// fraction of the genome over which the samples have
+    /// a single root.
+    /// See [`EvolvableTableCollection::coalescence_fraction_history`].
+    ///
+    /// Each record builds a tree sequence from a copy of the tables.
+    pub fn track_coalescence_fraction(&mut self, track: bool) {
+        self.track_coalescence_fraction = track;
+    }
+
+    /// `(time, fraction of the genome with a single root)`
+    /// for each simplification done while tracking was enabled.
+    pub fn coalescence_fraction_history(&self) -> &[(LargeSignedInteger, f64)] {
+        &self.coalescence_fraction_history
+    }
 
+    /// Record the birth of a diploid as two nodes that share
+    /// a new individual.
+    ///
+    /// `maternal` and `paternal` are the transmitted segments of
+    /// the two genome copies, each recorded as by
+    /// [`EvolveAncestry::record_birth`], so a diploid birth
+    /// replaces two alive nodes.
+    pub fn record_diploid_birth(
+        &mut self,
+        birth_time: LargeSignedInteger,
+        final_timepoint: LargeSignedInteger,
+        maternal: &[neutral_evolution::TransmittedSegment],
+        paternal: &[neutral_evolution::TransmittedSegment],
+    ) -> Result<IndividualId, Box<dyn std::error::Error>> {
+        for b in maternal.iter().chain(paternal.iter()) {
+            b.validate()?;
+        }
+        let individual =
             self.tables
-                .check_integrity(tskit::TableIntegrityCheckFlags::CHECK_EDGE_ORDERING)?;
-
-            // if self.bookmark.offsets.edges > 0 {
-            //     // To simplify, the edge table must
-            //     // have the newest edges at the front.
-            //     // Sorting using a bookmark defines where
-            //     // to start sorting FROM.  So, we need to rotate
-            //     // each column
-
-            //     let num_edges = usize::from(self.tables.edges().num_rows());
-
-            //     // Get the raw pointer to the tsk_table_collection_t
-            //     let table_ptr = self.tables.as_mut_ptr();
-
-            //     let offset = usize::try_from(self.bookmark.offsets.edges)?;
-
-            //     // SAFETY: the tskit::TableCollection does not
-            //     // allow the managed pointer to be NULL
-            //     unsafe {
-            //         // For each column (that we are using), put the newest edges at the front.
-            //         rotate_left((*table_ptr).edges.parent, num_edges, offset);
-            //         rotate_left((*table_ptr).edges.child, num_edges, offset);
-            //         rotate_left((*table_ptr).edges.left, num_edges, offset);
-            //         rotate_left((*table_ptr).edges.right, num_edges, offset);
-            //     }
-            // }
-            let idmap = match self.tables.simplify(
-                &self.alive_nodes,
-                tskit::SimplificationOptions::default(),
-                true,
-            ) {
-                Err(e) => return Err(Box::new(e)),
-                Ok(x) => x.unwrap(),
+                .add_individual(0, &[] as &[tskit::Location], &[] as &[IndividualId])?;
+        self.record_birth_details(birth_time, final_timepoint, maternal, individual)?;
+        self.record_birth_details(birth_time, final_timepoint, paternal, individual)?;
+        Ok(individual)
+    }
+
+    /// Simplify with respect to `n` distinct alive nodes, chosen
+    /// at random, plus any preserved and persistent nodes.
+    ///
+    /// The chosen nodes become the alive nodes, in their previous
+    /// order, and the population size becomes `n`.
+    /// The choice depends only on the state of `rng`, so runs
+    /// with the same seed give identical tables.
+    /// Call this between time steps, typically after
+    /// [`EvolveAncestry::finish`].
+    /// If simplification fails, the alive nodes are unchanged.
+    pub fn subsample<R: rand::Rng + ?Sized>(
+        &mut self,
+        n: usize,
+        rng: &mut R,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.enact_replacements();
+        if n > self.alive_nodes.len() {
+            return Err(format!(
+                "cannot subsample {} of {} alive nodes",
+                n,
+                self.alive_nodes.len()
+            )
+            .into());
+        }
+        let mut chosen = rand::seq::index::sample(rng, self.alive_nodes.len(), n).into_vec();
+        chosen.sort_unstable();
+        let subsample = chosen.iter().map(|&i| self.alive_nodes[i]).collect();
+        let alive_nodes = std::mem::replace(&mut self.alive_nodes, subsample);
+        if let Err(e) = self.simplify_tables() {
+            self.alive_nodes = alive_nodes;
+            return Err(e);
+        }
+        self.popsize = SignedInteger::try_from(n)?;
+        Ok(())
+    }
+
+    /// The node id map from the most recent simplification,
+    /// or `None` if the tables have not yet been simplified.
+    ///
+    /// Indexed by input node id; the value is the output node id,
+    /// which is [`NodeId::NULL`] for nodes removed by simplification.
+    pub fn last_idmap(&self) -> Option<&[NodeId]> {
+        self.last_time_simplified.map(|_| self.idmap.as_slice())
+    }
+
+    /// Count the edges overlapping each of `bins` equal-width
+    /// genomic windows.
+    ///
+    /// All recorded edges are counted, including those of births
+    /// since the last simplification, whether they are buffered
+    /// or already in the edge table.  Call this right after a
+    /// simplification, such as after
+    /// [`EvolveAncestry::finish`], to count only the edges of
+    /// the simplified tree sequence.
+    ///
+    /// Fails if `bins` is 0.
+    pub fn edge_density(&self, bins: usize) -> Result<Vec<usize>, tskit::TskitError> {
+        if bins == 0 {
+            return Err(tskit::TskitError::ValueError {
+                got: "0 bins".to_string(),
+                expected: "at least one bin".to_string(),
+            });
+        }
+        let sequence_length = f64::from(self.tables.sequence_length());
+        let width = sequence_length / bins as f64;
+        let mut density = vec![0; bins];
+        let edges = self
+            .tables
+            .edges_iter()
+            .map(|e| (e.left, e.right))
+            .chain(self.edge_buffer.iter().map(|e| (e.left, e.right)));
+        for (left, right) in edges {
+            let (left, right) = (f64::from(left), f64::from(right));
+            let first = (left / width) as usize;
+            // Windows are half-open, so an edge ending
+            // on a window boundary does not enter that window.
+            let last = ((right / width).ceil() as usize).min(bins);
+            for d in &mut density[first..last] {
+                *d += 1;
+            }
+        }
+        Ok(density)
+    }
+
+    // Add a node belonging to `individual` and buffer
+    // (or add) its edges.
+    fn record_birth_details(
+        &mut self,
+        birth_time: LargeSignedInteger,
+        final_timepoint: LargeSignedInteger,
+        breakpoints: &[neutral_evolution::TransmittedSegment],
+        individual: IndividualId,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for b in breakpoints {
+            b.validate()?;
+        }
+        let t = Time::from((final_timepoint - birth_time) as f64);
+        let child = self
+            .tables
+            .add_node(0, t, self.current_population, individual)?;
+        for b in breakpoints {
+            #[cfg(debug_assertions)]
+            {
+                let ctime = self.tables.nodes().time(child).unwrap();
+                let ptime = self
+                    .tables
+                    .nodes()
+                    .time(NodeId::from(self.alive_nodes[b.parent]))
+                    .unwrap();
+                assert!(
+                    ctime < ptime,
+                    "{} {}, {} {}",
+                    ctime,
+                    ptime,
+                    final_timepoint,
+                    birth_time
+                );
+            }
+            let edge = BufferedEdge {
+                left: Position::from(b.left as f64),
+                right: Position::from(b.right as f64),
+                parent: self.alive_nodes[b.parent],
+                child,
             };
-            self.last_time_simplified = Some(current_time_point);
+            match self.sorting_mode {
+                SortingMode::FullSort => {
+                    self.tables
+                        .add_edge(edge.left, edge.right, edge.parent, edge.child)?;
+                }
+                SortingMode::EdgeBuffer => self.edge_buffer.push(edge),
+            }
+        }
+        self.births.push(child);
 
-            // next time, we will only sort the new edges
-            // TODO: try to restore this
-            // self.bookmark.offsets.edges = u64::from(self.tables.edges().num_rows());
+        Ok(())
+    }
 
-            // remap the alive nodes
-            for alive in self.alive_nodes.iter_mut() {
-                *alive = idmap[usize::from(*alive)];
-                assert!(!alive.is_null());
+    fn simplification_due(&self, current_time_point: LargeSignedInteger) -> bool {
+        current_time_point % self.simplification_interval == 0
+    }
+
+    // The alive, preserved, and persistent nodes,
+    // without duplicates.
+    fn sample_nodes(&self) -> Vec<NodeId> {
+        let mut samples = self.alive_nodes.clone();
+        for p in self
+            .preserved_nodes
+            .iter()
+            .chain(self.persistent_samples.iter())
+        {
+            if !samples.contains(p) {
+                samples.push(*p);
             }
+        }
+        samples
+    }
 
-            let num_samples = self
-                .tables
-                .nodes_iter()
-                .filter(|n| n.flags.contains(tskit::NodeFlags::IS_SAMPLE))
-                .count();
-            assert_eq!(num_samples as i32, self.popsize);
+    /// Flag the alive, preserved, and persistent nodes as
+    /// samples, and clear the sample flag of all other nodes.
+    ///
+    /// Simplification keeps these flags up to date, so this is
+    /// only needed to convert to a tree sequence mid-run.
+    /// Call it between time steps, after simplification.
+    pub fn flag_current_samples(&mut self) {
+        let samples = self.sample_nodes();
+        let mut nodes = self.tables.nodes();
+        let flags = nodes.flags_array_mut();
+        for f in flags.iter_mut() {
+            f.remove(tskit::NodeFlags::IS_SAMPLE);
+        }
+        for s in samples {
+            flags[usize::from(s)].insert(tskit::NodeFlags::IS_SAMPLE);
+        }
+    }
+
+    // Sort the edges and simplify, returning the node id map.
+    fn sort_and_simplify(
+        &mut self,
+        samples: &[NodeId],
+    ) -> Result<Vec<NodeId>, Box<dyn std::error::Error>> {
+        if self.sorting_mode == SortingMode::FullSort || self.bookmark_invalidated {
+            for e in self.edge_buffer.drain(..) {
+                self.tables.add_edge(e.left, e.right, e.parent, e.child)?;
+            }
+            self.tables.full_sort(tskit::TableSortOptions::default())?;
+        } else {
+            self.flush_edge_buffer()?;
+            self.sort_sites_and_mutations()?;
+        }
+
+        self.tables
+            .check_integrity(tskit::TableIntegrityCheckFlags::CHECK_EDGE_ORDERING)?;
+
+        // if self.bookmark.offsets.edges > 0 {
+        //     // To simplify, the edge table must
+        //     // have the newest edges at the front.
+        //     // Sorting using a bookmark defines where
+        //     // to start sorting FROM.  So, we need to rotate
+        //     // each column
+
+        //     let num_edges = usize::from(self.tables.edges().num_rows());
+
+        //     // Get the raw pointer to the tsk_table_collection_t
+        //     let table_ptr = self.tables.as_mut_ptr();
+
+        //     let offset = usize::try_from(self.bookmark.offsets.edges)?;
+
+        //     // SAFETY: the tskit::TableCollection does not
+        //     // allow the managed pointer to be NULL
+        //     unsafe {
+        //         // For each column (that we are using), put the newest edges at the front.
+        //         rotate_left((*table_ptr).edges.parent, num_edges, offset);
+        //         rotate_left((*table_ptr).edges.child, num_edges, offset);
+        //         rotate_left((*table_ptr).edges.left, num_edges, offset);
+        //         rotate_left((*table_ptr).edges.right, num_edges, offset);
+        //     }
+        // }
+        let idmap = self
+            .tables
+            .simplify(samples, tskit::SimplificationOptions::default(), true)?
+            .unwrap()
+            .to_vec();
+        self.compute_mutation_parents()?;
+        Ok(idmap)
+    }
+
+    // The fraction of the genome over which the
+    // current samples have a single root.
+    fn coalescence_fraction(&self) -> Result<f64, tskit::TskitError> {
+        let ts = self
+            .tables
+            .deepcopy()?
+            .tree_sequence(tskit::TreeSequenceFlags::BUILD_INDEXES)?;
+        let mut span = 0.0;
+        let mut tree_iter = ts.tree_iterator(tskit::TreeFlags::default())?;
+        while let Some(tree) = tree_iter.next() {
+            if tree.roots().count() == 1 {
+                let (left, right) = tree.interval();
+                span += f64::from(right) - f64::from(left);
+            }
+        }
+        Ok(span / f64::from(self.tables.sequence_length()))
+    }
+
+    // Simplify if `force` is `true` or if simplification is due.
+    fn simplify_details(
+        &mut self,
+        current_time_point: LargeSignedInteger,
+        force: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.enact_replacements();
+        self.current_time_point = current_time_point;
+        if current_time_point > 0 && (force || self.simplification_due(current_time_point)) {
+            self.simplify_tables()?;
+            self.last_time_simplified = Some(current_time_point);
+            if self.track_coalescence_fraction {
+                let fraction = self.coalescence_fraction()?;
+                self.coalescence_fraction_history
+                    .push((current_time_point, fraction));
+            }
             Ok(())
         } else {
             Ok(())
         }
     }
+
+    // Simplify with respect to the current sample nodes
+    // and remap the stored node ids.
+    // Invalid sample ids are reported before anything changes.
+    // If sorting or simplification fails, the buffered edges
+    // have already been moved into the edge table, so the buffer
+    // stays empty.  The site ids are refreshed and the next
+    // simplification sorts all of the tables, which leaves
+    // the collection usable once the cause is fixed.
+    fn simplify_tables(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let samples = self.sample_nodes();
+        let num_nodes = usize::try_from(self.tables.nodes().num_rows())?;
+        if let Some(bad) = samples
+            .iter()
+            .find(|s| s.is_null() || usize::from(**s) >= num_nodes)
+        {
+            return Err(format!("invalid sample node id {}", bad).into());
+        }
+        let num_edges_before =
+            usize::try_from(self.tables.edges().num_rows())? + self.edge_buffer.len();
+        let idmap = match self.sort_and_simplify(&samples) {
+            Ok(idmap) => idmap,
+            Err(e) => {
+                self.refresh_site_ids();
+                self.bookmark_invalidated = true;
+                return Err(e);
+            }
+        };
+        self.bookmark_invalidated = false;
+        let num_edges_after = usize::try_from(self.tables.edges().num_rows())?;
+        // Simplification may split edges as well as remove them.
+        self.last_edge_diff = (
+            num_edges_before - self.num_edges_after_simplify
+                + num_edges_after.saturating_sub(num_edges_before),
+            num_edges_before.saturating_sub(num_edges_after),
+        );
+        self.num_edges_after_simplify = num_edges_after;
+
+        // next time, we will only sort the new edges
+        // TODO: try to restore this
+        // self.bookmark.offsets.edges = u64::from(self.tables.edges().num_rows());
+
+        // remap the alive nodes
+        for alive in self.alive_nodes.iter_mut() {
+            *alive = idmap[usize::from(*alive)];
+            assert!(!alive.is_null());
+        }
+        for preserved in self
+            .preserved_nodes
+            .iter_mut()
+            .chain(self.persistent_samples.iter_mut())
+        {
+            *preserved = idmap[usize::from(*preserved)];
+            assert!(!preserved.is_null());
+        }
+        self.idmap = idmap;
+
+        let num_samples = self
+            .tables
+            .nodes_iter()
+            .filter(|n| n.flags.contains(tskit::NodeFlags::IS_SAMPLE))
+            .count();
+        assert_eq!(num_samples, samples.len());
+        Ok(())
+    }
 }
 
 unsafe fn rotate_left<T>(data: *mut T, len: usize, mid: usize) {
@@ -145,18 +941,40 @@ impl EvolveAncestry for EvolvableTableCollection {
     }
 
     fn setup(&mut self, final_time: LargeSignedInteger) {
+        let founder_time = self.founder_time.unwrap_or(final_time);
+        assert!(
+            founder_time >= final_time,
+            "founder time {} is younger than the final time {}",
+            founder_time,
+            final_time
+        );
+        let time = Time::from(founder_time as f64);
+        let flags = if self.founders_are_samples {
+            tskit::NodeFlags::IS_SAMPLE
+        } else {
+            tskit::NodeFlags::NONE
+        };
         for i in 0..self.current_population_size() {
-            let id = self
-                .tables
-                .add_node(
-                    0,
-                    Time::from(final_time as f64),
-                    PopulationId::NULL,
+            let id = match self.founder_metadata.get(i) {
+                Some(md) => self.tables.add_node_with_metadata(
+                    flags,
+                    time,
+                    self.current_population,
                     IndividualId::NULL,
-                )
-                .unwrap();
+                    md,
+                ),
+                None => {
+                    self.tables
+                        .add_node(flags, time, self.current_population, IndividualId::NULL)
+                }
+            }
+            .unwrap();
             self.alive_nodes.push(id);
         }
+        if self.founders_are_samples {
+            self.preserved_nodes
+                .extend(self.alive_nodes.iter().copied());
+        }
     }
 
     fn generate_deaths(&mut self, death: &mut neutral_evolution::Death) -> usize {
@@ -179,38 +997,7 @@ impl EvolveAncestry for EvolvableTableCollection {
         final_timepoint: LargeSignedInteger,
         breakpoints: &[neutral_evolution::TransmittedSegment],
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let t = Time::from((final_timepoint - birth_time) as f64);
-        let child = self
-            .tables
-            .add_node(0, t, PopulationId::NULL, IndividualId::NULL)?;
-        for b in breakpoints {
-            #[cfg(debug_assertions)]
-            {
-                let ctime = self.tables.nodes().time(child).unwrap();
-                let ptime = self
-                    .tables
-                    .nodes()
-                    .time(NodeId::from(self.alive_nodes[b.parent]))
-                    .unwrap();
-                assert!(
-                    ctime < ptime,
-                    "{} {}, {} {}",
-                    ctime,
-                    ptime,
-                    final_timepoint,
-                    birth_time
-                );
-            }
-            self.tables.add_edge(
-                Position::from(b.left as f64),
-                Position::from(b.right as f64),
-                self.alive_nodes[b.parent],
-                child,
-            )?;
-        }
-        self.births.push(child);
-
-        Ok(())
+        self.record_birth_details(birth_time, final_timepoint, breakpoints, IndividualId::NULL)
     }
 
     fn simplify(
@@ -234,12 +1021,815 @@ impl EvolveAncestry for EvolvableTableCollection {
             }
             None => self.simplify_details(current_time_point, true),
         };
-        for i in self.alive_nodes.iter() {
-            self.tables.nodes().flags_array_mut()[usize::from(*i)] = tskit::NodeFlags::IS_SAMPLE;
-        }
-        rv
+        self.flag_current_samples();
+        rv?;
+        // Mutations may have been added since the last simplification.
+        self.sort_sites_and_mutations()?;
+        self.compute_mutation_parents()?;
+        Ok(())
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use neutral_evolution::TransmittedSegment;
+
+    #[test]
+    fn test_invalid_simplification_interval() {
+        for interval in [0, -1] {
+            let e = EvolvableTableCollection::new(100, 2, interval)
+                .err()
+                .unwrap();
+            assert!(e
+                .downcast_ref::<neutral_evolution::ParameterError>()
+                .is_some());
+        }
+        let tables = EvolvableTableCollection::new(100, 2, 1).unwrap();
+        assert!((1..10).all(|t| tables.simplification_due(t)));
+    }
+
+    #[test]
+    fn test_try_genome_length() {
+        let mut tables = EvolvableTableCollection::new(100, 2, 1).unwrap();
+        assert_eq!(tables.try_genome_length().unwrap(), 100);
+        tables.tables = TableCollection::new(100.5).unwrap();
+        assert!(tables.try_genome_length().is_err());
+        assert_eq!(tables.genome_length(), 100);
+    }
+
+    #[test]
+    fn test_edge_density() {
+        for mode in [SortingMode::FullSort, SortingMode::EdgeBuffer] {
+            let mut tables = EvolvableTableCollection::new(100, 2, 1000).unwrap();
+            tables.set_sorting_mode(mode);
+            tables.setup(10);
+            tables.replacements = vec![0, 1];
+            tables
+                .record_birth(
+                    1,
+                    10,
+                    &[
+                        TransmittedSegment::new(0, 55, 0),
+                        TransmittedSegment::new(55, 100, 1),
+                    ],
+                )
+                .unwrap();
+            tables
+                .record_birth(1, 10, &[TransmittedSegment::new(0, 100, 0)])
+                .unwrap();
+
+            // The window containing the breakpoint
+            // overlaps the edges on either side of it.
+            let mut expected = vec![2; 10];
+            expected[5] = 3;
+            assert_eq!(tables.edge_density(10).unwrap(), expected);
+            assert_eq!(tables.edge_density(1).unwrap(), vec![3]);
+            assert!(tables.edge_density(0).is_err());
+
+            // After simplification, only the simplified edges remain.
+            tables.simplify_now(1).unwrap();
+            let num_edges = usize::try_from(tables.tables.edges().num_rows()).unwrap();
+            assert_eq!(tables.edge_density(1).unwrap(), vec![num_edges]);
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Deme {
+        name: String,
+    }
+
+    impl tskit::metadata::MetadataRoundtrip for Deme {
+        fn encode(&self) -> Result<Vec<u8>, tskit::metadata::MetadataError> {
+            Ok(self.name.as_bytes().to_vec())
+        }
+
+        fn decode(md: &[u8]) -> Result<Self, tskit::metadata::MetadataError> {
+            Ok(Self {
+                name: String::from_utf8(md.to_vec()).unwrap(),
+            })
+        }
+    }
+
+    impl tskit::metadata::PopulationMetadata for Deme {}
+
+    #[test]
+    fn test_populations() {
+        let mut tables = EvolvableTableCollection::new(100, 2, 1000).unwrap();
+        let demes = [
+            Deme {
+                name: "source".to_string(),
+            },
+            Deme {
+                name: "sink".to_string(),
+            },
+        ];
+        let source = tables.add_population(&demes[0]).unwrap();
+        let sink = tables.add_population(&demes[1]).unwrap();
+        tables.set_current_population(source);
+        tables.setup(10);
+        tables.set_current_population(sink);
+        tables.replacements = vec![0, 1];
+        tables
+            .record_birth(
+                1,
+                10,
+                &[
+                    TransmittedSegment::new(0, 50, 0),
+                    TransmittedSegment::new(50, 100, 1),
+                ],
+            )
+            .unwrap();
+        tables
+            .record_birth(1, 10, &[TransmittedSegment::new(0, 100, 0)])
+            .unwrap();
+        tables.simplify_now(1).unwrap();
+
+        let ts = tskit::TreeSequence::try_from(tables).unwrap();
+        assert_eq!(ts.populations().num_rows(), 2);
+        for (id, deme) in [source, sink].into_iter().zip(demes.iter()) {
+            let md = ts.populations().metadata::<Deme>(id).unwrap().unwrap();
+            assert_eq!(md, *deme);
+        }
+        for node in ts.nodes_iter() {
+            if node.flags.is_sample() {
+                assert_eq!(node.population, sink);
+            } else {
+                assert_eq!(node.population, source);
+            }
+        }
+    }
+
+    // Replace everyone, with deterministic inheritance.
+    fn nonoverlapping_generation(
+        tables: &mut EvolvableTableCollection,
+        birth_time: LargeSignedInteger,
+        final_time: LargeSignedInteger,
+    ) {
+        let popsize = tables.current_population_size();
+        tables.replacements = (0..popsize).collect();
+        for i in 0..popsize {
+            tables
+                .record_birth(
+                    birth_time,
+                    final_time,
+                    &[
+                        TransmittedSegment::new(0, 50, i / 2),
+                        TransmittedSegment::new(50, 100, (i + 1) % popsize),
+                    ],
+                )
+                .unwrap();
+        }
+        tables.simplify(birth_time).unwrap();
+    }
+
+    #[test]
+    fn test_census() {
+        let final_time = 10;
+        let mut tables = EvolvableTableCollection::new(100, 10, 3).unwrap();
+        tables.setup(final_time);
+        let mut census = vec![];
+        for birth_time in 1..final_time + 1 {
+            nonoverlapping_generation(&mut tables, birth_time, final_time);
+            if birth_time == 4 {
+                assert!(tables.add_census(3).is_err());
+                census = tables.add_census(4).unwrap();
+            }
+        }
+        tables.finish(final_time).unwrap();
+        assert_eq!(census.len(), 10);
+        assert_eq!(tables.preserved_nodes().len(), 10);
+
+        let ts = tskit::TreeSequence::try_from(tables).unwrap();
+        let mut num_census = 0;
+        let mut num_samples = 0;
+        for node in ts.nodes_iter() {
+            if node.flags.bits() & CENSUS_NODE != 0 {
+                num_census += 1;
+                assert!(node.flags.is_sample());
+                assert_eq!(f64::from(node.time), (final_time - 4) as f64);
+            }
+            if node.flags.is_sample() {
+                num_samples += 1;
+            }
+        }
+        assert_eq!(num_census, 10);
+        assert_eq!(num_samples, 20);
+    }
+
+    #[test]
+    fn test_persistent_samples() {
+        let final_time = 20;
+        let mut tables = EvolvableTableCollection::new(100, 10, 2).unwrap();
+        tables.setup(final_time);
+        let mut persistent_time = None;
+        for birth_time in 1..final_time + 1 {
+            nonoverlapping_generation(&mut tables, birth_time, final_time);
+            if birth_time == 3 {
+                let nodes = tables.alive_nodes[..2].to_vec();
+                tables.set_persistent_samples(&nodes);
+                persistent_time = Some(tables.tables.nodes().time(nodes[0]).unwrap());
+            }
+            for p in tables.persistent_samples() {
+                assert_eq!(
+                    tables.tables.nodes().time(*p).unwrap(),
+                    persistent_time.unwrap()
+                );
+            }
+        }
+        tables.finish(final_time).unwrap();
+        assert_eq!(tables.persistent_samples().len(), 2);
+        assert!(tables.preserved_nodes().is_empty());
+
+        let persistent = tables.persistent_samples().to_vec();
+        let ts = tskit::TreeSequence::try_from(tables).unwrap();
+        let samples = ts
+            .nodes_iter()
+            .filter(|n| n.flags.is_sample())
+            .map(|n| n.id)
+            .collect::<Vec<_>>();
+        assert_eq!(samples.len(), 12);
+        assert!(persistent.iter().all(|p| samples.contains(p)));
+    }
+
+    #[test]
+    fn test_zero_length_transmission() {
+        use neutral_evolution::TransmissionError;
+
+        let mut tables = EvolvableTableCollection::new(100, 2, 1000).unwrap();
+        tables.setup(10);
+        let e = tables
+            .record_birth(
+                1,
+                10,
+                &[
+                    TransmittedSegment::new(0, 50, 0),
+                    TransmittedSegment::new(50, 50, 1),
+                    TransmittedSegment::new(50, 100, 1),
+                ],
+            )
+            .unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<TransmissionError>(),
+            Some(&TransmissionError::ZeroLengthSegment { position: 50 })
+        );
+        assert_eq!(tables.tables.nodes().num_rows(), 2);
+        assert_eq!(tables.tables.edges().num_rows(), 0);
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Label {
+        name: String,
+    }
+
+    impl tskit::metadata::MetadataRoundtrip for Label {
+        fn encode(&self) -> Result<Vec<u8>, tskit::metadata::MetadataError> {
+            Ok(format!("{{\"name\":\"{}\"}}", self.name).into_bytes())
+        }
+
+        fn decode(md: &[u8]) -> Result<Self, tskit::metadata::MetadataError> {
+            let s = String::from_utf8(md.to_vec()).unwrap();
+            let name = s
+                .trim_start_matches("{\"name\":\"")
+                .trim_end_matches("\"}")
+                .to_string();
+            Ok(Self { name })
+        }
+    }
+
+    impl tskit::metadata::NodeMetadata for Label {}
+
+    #[test]
+    fn test_founder_metadata() {
+        let schema = r#"{"codec":"json","type":"object","properties":{"name":{"type":"string"}}}"#;
+        let labels = (0..10)
+            .map(|i| Label {
+                name: format!("founder{}", i),
+            })
+            .collect::<Vec<_>>();
+
+        let mut tables = EvolvableTableCollection::new(100, 10, 1000).unwrap();
+        assert!(tables.set_founder_metadata(&labels).is_err());
+        tables.set_node_metadata_schema(schema).unwrap();
+        assert!(tables.set_founder_metadata(&labels[1..]).is_err());
+        tables.set_founder_metadata(&labels).unwrap();
+
+        let final_time = 2;
+        tables.setup(final_time);
+        for birth_time in 1..final_time + 1 {
+            nonoverlapping_generation(&mut tables, birth_time, final_time);
+        }
+        tables.finish(final_time).unwrap();
+
+        // SAFETY: the tskit::TableCollection does not
+        // allow the managed pointer to be NULL
+        let output_schema = unsafe {
+            let nodes = &(*tables.tables.as_ptr()).nodes;
+            std::slice::from_raw_parts(
+                nodes.metadata_schema as *const u8,
+                nodes.metadata_schema_length as usize,
+            )
+            .to_vec()
+        };
+        assert_eq!(output_schema, schema.as_bytes());
+
+        let mut num_founders = 0;
+        for node in tables.tables.nodes_iter() {
+            let md = tables.tables.nodes().metadata::<Label>(node.id).unwrap();
+            if f64::from(node.time) == final_time as f64 {
+                num_founders += 1;
+                assert!(labels.contains(&md.unwrap()));
+            } else {
+                assert!(md.is_none());
+            }
+        }
+        assert!(num_founders > 0);
+    }
+
+    #[test]
+    fn test_founder_time() {
+        let final_time = 2;
+        let founder_time = 7;
+        let mut tables = EvolvableTableCollection::new(100, 10, 1000).unwrap();
+        tables.set_founder_time(founder_time).unwrap();
+        tables.setup(final_time);
+        assert!(tables.set_founder_time(final_time).is_err());
+        for birth_time in 1..final_time + 1 {
+            nonoverlapping_generation(&mut tables, birth_time, final_time);
+        }
+        tables.finish(final_time).unwrap();
+
+        let ts = tskit::TreeSequence::try_from(tables).unwrap();
+        let time = |n| f64::from(ts.nodes().time(n).unwrap());
+        let mut num_founders = 0;
+        for node in ts.nodes_iter() {
+            if f64::from(node.time) == founder_time as f64 {
+                num_founders += 1;
+            } else {
+                assert!(f64::from(node.time) < final_time as f64);
+            }
+        }
+        assert!(num_founders > 0);
+        for e in ts.edges_iter() {
+            assert!(time(e.parent) > time(e.child));
+        }
+    }
+
+    #[test]
+    fn test_founders_are_samples() {
+        let final_time = 6;
+        let mut tables = EvolvableTableCollection::new(100, 10, 2).unwrap();
+        tables.set_founders_are_samples(true).unwrap();
+        tables.setup(final_time);
+        assert!(tables.set_founders_are_samples(false).is_err());
+        assert!(tables
+            .tables
+            .nodes_iter()
+            .all(|n| n.flags.contains(tskit::NodeFlags::IS_SAMPLE)));
+        for birth_time in 1..final_time + 1 {
+            nonoverlapping_generation(&mut tables, birth_time, final_time);
+            if birth_time == 3 {
+                tables.flag_current_samples();
+                let num_samples = tables
+                    .tables
+                    .nodes_iter()
+                    .filter(|n| n.flags.contains(tskit::NodeFlags::IS_SAMPLE))
+                    .count();
+                assert_eq!(num_samples, 20);
+            }
+        }
+        tables.finish(final_time).unwrap();
+
+        let ts = tskit::TreeSequence::try_from(tables).unwrap();
+        let mut num_founders = 0;
+        for node in ts.nodes_iter() {
+            if f64::from(node.time) == final_time as f64 {
+                assert!(node.flags.is_sample());
+                num_founders += 1;
+            }
+        }
+        assert_eq!(num_founders, 10);
+        assert_eq!(ts.sample_nodes().len(), 20);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_founder_time_younger_than_final_time() {
+        let mut tables = EvolvableTableCollection::new(100, 10, 1000).unwrap();
+        tables.set_founder_time(1).unwrap();
+        tables.setup(2);
+    }
+
+    #[test]
+    fn test_flag_current_samples() {
+        let final_time = 10;
+        let mut tables = EvolvableTableCollection::new(100, 10, 1).unwrap();
+        tables.setup(final_time);
+        for birth_time in 1..6 {
+            nonoverlapping_generation(&mut tables, birth_time, final_time);
+            if birth_time == 2 {
+                tables.add_census(2).unwrap();
+            }
+        }
+        tables.flag_current_samples();
+        // Flagging twice is harmless.
+        tables.flag_current_samples();
+        let mut expected = tables.alive_nodes.clone();
+        expected.extend_from_slice(tables.preserved_nodes());
+        expected.sort();
+
+        let ts = tskit::TreeSequence::try_from(tables).unwrap();
+        let mut samples = ts.sample_nodes().to_vec();
+        samples.sort();
+        assert_eq!(samples, expected);
+        assert_eq!(samples.len(), 20);
+    }
+
+    #[test]
+    fn test_failed_simplification_restores_tables() {
+        for mode in [SortingMode::FullSort, SortingMode::EdgeBuffer] {
+            let final_time = 10;
+            let mut tables = EvolvableTableCollection::new(100, 10, 1000).unwrap();
+            tables.set_sorting_mode(mode);
+            tables.setup(final_time);
+            for birth_time in 1..4 {
+                nonoverlapping_generation(&mut tables, birth_time, final_time);
+            }
+            let before = tables.tables.deepcopy().unwrap();
+            let num_buffered = tables.edge_buffer.len();
+
+            // An invalid sample makes simplification fail.
+            tables.persistent_samples = vec![NodeId::from(1000000)];
+            assert!(tables.simplify_now(3).is_err());
+            assert!(tables
+                .tables
+                .equals(&before, tskit::TableEqualityOptions::default()));
+            assert_eq!(tables.edge_buffer.len(), num_buffered);
+
+            tables.persistent_samples.clear();
+
+            // An edge whose parent is younger than its child
+            // makes simplification itself fail.
+            let clean = tables.tables.deepcopy().unwrap();
+            tables
+                .tables
+                .add_edge(0., 100., tables.alive_nodes[0], NodeId::from(0))
+                .unwrap();
+            let before = tables.tables.deepcopy().unwrap();
+            assert!(tables.simplify_now_checked(3).is_err());
+            assert!(tables
+                .tables
+                .equals(&before, tskit::TableEqualityOptions::default()));
+            assert_eq!(tables.edge_buffer.len(), num_buffered);
+            tables.tables = clean;
+
+            for birth_time in 4..final_time + 1 {
+                nonoverlapping_generation(&mut tables, birth_time, final_time);
+            }
+            tables.finish(final_time).unwrap();
+            let ts = tskit::TreeSequence::try_from(tables).unwrap();
+            assert_eq!(ts.sample_nodes().len(), 10);
+        }
+    }
+
+    #[test]
+    fn test_failed_simplification_leaves_tables_usable() {
+        for mode in [SortingMode::FullSort, SortingMode::EdgeBuffer] {
+            let final_time = 10;
+            let run = |fail: bool| {
+                let mut tables = EvolvableTableCollection::new(100, 10, 1000).unwrap();
+                tables.set_sorting_mode(mode);
+                tables.setup(final_time);
+                for birth_time in 1..4 {
+                    nonoverlapping_generation(&mut tables, birth_time, final_time);
+                }
+                // Sites out of position order, so that sorting moves them.
+                tables
+                    .add_mutation(tables.alive_nodes[0], 50, b"1")
+                    .unwrap();
+                tables
+                    .add_mutation(tables.alive_nodes[0], 10, b"1")
+                    .unwrap();
+                if fail {
+                    let (parent, child) = (tables.alive_nodes[0], NodeId::from(0));
+                    tables.tables.add_edge(0., 100., parent, child).unwrap();
+                    assert!(tables.simplify_now(3).is_err());
+                    assert!(tables.edge_buffer.is_empty());
+                    for site in tables.tables.sites_iter() {
+                        let position = f64::from(site.position) as LargeSignedInteger;
+                        assert_eq!(tables.site_ids[&position], site.id);
+                    }
+                    retain_edges(&mut tables, |e| e.parent != parent || e.child != child);
+                }
+                tables.simplify_now(3).unwrap();
+                let edges = tables
+                    .tables
+                    .edges_iter()
+                    .map(|e| {
+                        (
+                            f64::from(e.left).to_bits(),
+                            f64::from(e.right).to_bits(),
+                            e.parent,
+                            e.child,
+                        )
+                    })
+                    .collect::<std::collections::HashSet<_>>();
+                assert_eq!(
+                    edges.len(),
+                    usize::try_from(tables.tables.edges().num_rows()).unwrap()
+                );
+                for birth_time in 4..final_time + 1 {
+                    nonoverlapping_generation(&mut tables, birth_time, final_time);
+                }
+                tables.finish(final_time).unwrap();
+                timed_edges(&tables)
+            };
+            assert_eq!(run(true), run(false));
+        }
+    }
+
+    #[test]
+    fn test_stacked_mutations() {
+        for mode in [SortingMode::FullSort, SortingMode::EdgeBuffer] {
+            let final_time = 6;
+            let mut tables = EvolvableTableCollection::new(100, 10, 2).unwrap();
+            tables.set_sorting_mode(mode);
+            tables.setup(final_time);
+            // The child at index 0 inherits [0, 50)
+            // from the parent at index 0.
+            for birth_time in 1..final_time + 1 {
+                nonoverlapping_generation(&mut tables, birth_time, final_time);
+                if birth_time == 2 {
+                    tables
+                        .add_mutation(tables.alive_nodes[0], 10, b"1")
+                        .unwrap();
+                }
+                if birth_time == 3 {
+                    tables
+                        .add_mutation(tables.alive_nodes[0], 10, b"2")
+                        .unwrap();
+                }
+            }
+            tables.finish(final_time).unwrap();
+
+            let ts = tskit::TreeSequence::try_from(tables).unwrap();
+            let sites = ts.sites();
+            let mutations = ts.mutations();
+            assert_eq!(sites.num_rows(), 1);
+            assert_eq!(mutations.num_rows(), 2);
+            let (first, second) = (MutationId::from(0), MutationId::from(1));
+            assert_eq!(mutations.derived_state(first).unwrap().unwrap(), b"1");
+            assert_eq!(mutations.derived_state(second).unwrap().unwrap(), b"2");
+            assert_eq!(mutations.parent(first).unwrap(), MutationId::NULL);
+            assert_eq!(mutations.parent(second).unwrap(), first);
+            assert!(mutations.time(first).unwrap() > mutations.time(second).unwrap());
+        }
+    }
+
+    // Edges as (left, right, parent time, child time), sorted.
+    fn timed_edges(tables: &EvolvableTableCollection) -> Vec<(f64, f64, f64, f64)> {
+        let nodes = tables.tables.nodes();
+        let time = |n| f64::from(nodes.time(n).unwrap());
+        let mut rv = tables
+            .tables
+            .edges_iter()
+            .map(|e| {
+                (
+                    f64::from(e.left),
+                    f64::from(e.right),
+                    time(e.parent),
+                    time(e.child),
+                )
+            })
+            .collect::<Vec<_>>();
+        rv.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        rv
+    }
+
+    // Reverse the order of the edge table.
+    fn reverse_edges(tables: &mut EvolvableTableCollection) {
+        let mut edges = tables.tables.edges_iter().collect::<Vec<_>>();
+        edges.reverse();
+        // SAFETY: the tskit::TableCollection does not
+        // allow the managed pointer to be NULL
+        unsafe {
+            tskit::bindings::tsk_edge_table_clear(&mut (*tables.tables.as_mut_ptr()).edges);
+        }
+        for e in edges {
+            tables
+                .tables
+                .add_edge(e.left, e.right, e.parent, e.child)
+                .unwrap();
+        }
+    }
+
+    // Keep only the edges for which `keep` is true.
+    fn retain_edges<F: Fn(&tskit::EdgeTableRow) -> bool>(
+        tables: &mut EvolvableTableCollection,
+        keep: F,
+    ) {
+        let edges = tables.tables.edges_iter().filter(keep).collect::<Vec<_>>();
+        // SAFETY: the tskit::TableCollection does not
+        // allow the managed pointer to be NULL
+        unsafe {
+            tskit::bindings::tsk_edge_table_clear(&mut (*tables.tables.as_mut_ptr()).edges);
+        }
+        for e in edges {
+            tables
+                .tables
+                .add_edge(e.left, e.right, e.parent, e.child)
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_invalidate_bookmark() {
+        let final_time = 10;
+        let run = |edit: bool, invalidate: bool| {
+            let mut tables = EvolvableTableCollection::new(100, 10, 2).unwrap();
+            tables.set_sorting_mode(SortingMode::EdgeBuffer);
+            tables.setup(final_time);
+            for birth_time in 1..final_time + 1 {
+                nonoverlapping_generation(&mut tables, birth_time, final_time);
+                if birth_time == 4 {
+                    if edit {
+                        reverse_edges(&mut tables);
+                    }
+                    if invalidate {
+                        tables.invalidate_bookmark();
+                    }
+                }
+            }
+            tables.finish(final_time).unwrap();
+            timed_edges(&tables)
+        };
+        let expected = run(false, false);
+        assert_eq!(run(true, true), expected);
+        assert_eq!(run(false, true), expected);
+
+        // Without invalidation, the edited edges are not sorted,
+        // so merging the edge buffer into them fails.
+        // The checked simplification leaves the tables usable.
+        let mut tables = EvolvableTableCollection::new(100, 10, 1000).unwrap();
+        tables.set_sorting_mode(SortingMode::EdgeBuffer);
+        tables.setup(final_time);
+        for birth_time in 1..5 {
+            nonoverlapping_generation(&mut tables, birth_time, final_time);
+            if birth_time == 2 {
+                tables.simplify_now(birth_time).unwrap();
+                reverse_edges(&mut tables);
+            }
+        }
+        assert!(tables.simplify_now_checked(4).is_err());
+        tables.invalidate_bookmark();
+        tables.simplify_now(4).unwrap();
+    }
+
+    #[test]
+    fn test_edge_diff_since_last_simplify() {
+        for mode in [SortingMode::FullSort, SortingMode::EdgeBuffer] {
+            let final_time = 10;
+            let mut tables = EvolvableTableCollection::new(100, 10, 1).unwrap();
+            tables.set_sorting_mode(mode);
+            tables.setup(final_time);
+            assert_eq!(tables.edge_diff_since_last_simplify(), (0, 0));
+            let mut num_edges = 0;
+            for birth_time in 1..3 {
+                nonoverlapping_generation(&mut tables, birth_time, final_time);
+                // Each of the 10 births has two edges.
+                let (added, removed) = tables.edge_diff_since_last_simplify();
+                assert_eq!(added, 20);
+                num_edges = num_edges + added - removed;
+                assert_eq!(
+                    num_edges,
+                    usize::try_from(tables.tables.edges().num_rows()).unwrap()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_simplify_now() {
+        let mut tables = EvolvableTableCollection::new(100, 2, 1000).unwrap();
+        tables.setup(10);
+        tables.replacements = vec![0, 1];
+        tables
+            .record_birth(
+                1,
+                10,
+                &[
+                    TransmittedSegment::new(0, 50, 0),
+                    TransmittedSegment::new(50, 100, 1),
+                ],
+            )
+            .unwrap();
+        tables
+            .record_birth(1, 10, &[TransmittedSegment::new(0, 100, 0)])
+            .unwrap();
+        assert_eq!(tables.tables.edges().num_rows(), 3);
+
+        // Not a multiple of the interval, so this is a no-op...
+        tables.simplify(1).unwrap();
+        assert!(tables.last_time_simplified.is_none());
+
+        // ...but this is not.
+        tables.simplify_now(1).unwrap();
+        assert_eq!(tables.tables.edges().num_rows(), 2);
+        assert_eq!(tables.last_time_simplified, Some(1));
+    }
+
+    #[test]
+    fn test_diploid_birth() {
+        let final_time = 1;
+        let mut tables = EvolvableTableCollection::new(100, 4, 1).unwrap();
+        tables.setup(final_time);
+        tables.replacements = (0..4).collect();
+        let mut individuals = vec![];
+        for i in 0..2 {
+            let individual = tables
+                .record_diploid_birth(
+                    1,
+                    final_time,
+                    &[TransmittedSegment::new(0, 100, i)],
+                    &[
+                        TransmittedSegment::new(0, 50, 2),
+                        TransmittedSegment::new(50, 100, 3),
+                    ],
+                )
+                .unwrap();
+            individuals.push(individual);
+        }
+        assert_ne!(individuals[0], individuals[1]);
+        tables.finish(final_time).unwrap();
+
+        let ts = tskit::TreeSequence::try_from(tables).unwrap();
+        assert_eq!(ts.individuals().num_rows(), 2);
+        let nodes = ts.nodes();
+        for (i, individual) in individuals.iter().enumerate() {
+            for j in [2 * i, 2 * i + 1] {
+                assert_eq!(
+                    nodes.individual(NodeId::from(j as i32)).unwrap(),
+                    *individual
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_subsample() {
+        use rand::SeedableRng;
+        let final_time = 10;
+        let subsample = |seed| {
+            let mut tables = EvolvableTableCollection::new(100, 100, 3).unwrap();
+            tables.setup(final_time);
+            for birth_time in 1..final_time + 1 {
+                nonoverlapping_generation(&mut tables, birth_time, final_time);
+            }
+            tables.finish(final_time).unwrap();
+            let alive = tables.alive_nodes().to_vec();
+            let mut rng = rand_pcg::Pcg64::seed_from_u64(seed);
+            assert!(tables.subsample(101, &mut rng).is_err());
+            assert_eq!(tables.alive_nodes(), alive);
+            tables.subsample(5, &mut rng).unwrap();
+            tables
+        };
+
+        let tables = subsample(42);
+        assert_eq!(tables.current_population_size(), 5);
+        assert_eq!(
+            tables.alive_nodes(),
+            (0..5).map(NodeId::from).collect::<Vec<_>>()
+        );
+        let tables = tskit::TableCollection::from(tables);
+        assert!(tables.equals(
+            &tskit::TableCollection::from(subsample(42)),
+            tskit::TableEqualityOptions::default()
+        ));
+        let ts = tables
+            .tree_sequence(tskit::TreeSequenceFlags::BUILD_INDEXES)
+            .unwrap();
+        assert_eq!(ts.sample_nodes().len(), 5);
+    }
+
+    #[test]
+    fn test_last_idmap() {
+        let mut tables = EvolvableTableCollection::new(100, 2, 1).unwrap();
+        tables.setup(10);
+        assert!(tables.last_idmap().is_none());
+        tables.replacements = vec![0, 1];
+        for _ in 0..2 {
+            tables
+                .record_birth(1, 10, &[TransmittedSegment::new(0, 100, 1)])
+                .unwrap();
+        }
+        let births = tables.births.clone();
+        tables.simplify(1).unwrap();
+
+        let idmap = tables.last_idmap().unwrap();
+        for (birth, alive) in births.iter().zip(tables.alive_nodes.iter()) {
+            assert_eq!(idmap[usize::from(*birth)], *alive);
+        }
+        // Founder 0 left no descendants
+        assert!(idmap[0].is_null());
+    }
+}
@@ -1,5 +1,5 @@
-use neutral_evolution::{evolve, Parameters};
-use tskit::TableAccess;
+use neutral_evolution::{evolve, Parameters, Timed};
+use tskit::prelude::*;
 use tskit_evolution::*;
 
 #[test]
@@ -25,3 +25,133 @@ fn test_simulation_round_trip_overlapping_gens() {
         }
     }
 }
+
+#[test]
+fn test_simulation_is_reproducible() {
+    for mode in [SortingMode::FullSort, SortingMode::EdgeBuffer] {
+        for pdeath in [0.5, 1.0] {
+            let run = || {
+                let mut t = EvolvableTableCollection::new(100, 10, 3).unwrap();
+                t.set_sorting_mode(mode);
+                let p = Parameters::new(pdeath, 1e-1, 100).unwrap();
+                evolve(101, p, &mut t).unwrap();
+                let expected = (0..10).map(NodeId::from).collect::<Vec<_>>();
+                assert_eq!(t.alive_nodes(), expected);
+                tskit::TableCollection::from(t)
+            };
+            let tables = run();
+            assert!(tables.equals(&run(), tskit::TableEqualityOptions::default()));
+        }
+    }
+}
+
+// Node labels of ancestral nodes depend on the input
+// edge order, so compare the trees via the times to the
+// MRCA of every pair of samples.
+fn pairwise_tmrca(ts: &tskit::TreeSequence) -> Vec<((f64, f64), Vec<f64>)> {
+    let tables = ts.dump_tables().unwrap();
+    let nodes = tables.nodes();
+    let mut rv = vec![];
+    let mut tree_iter = ts.tree_iterator(tskit::TreeFlags::default()).unwrap();
+    while let Some(tree) = tree_iter.next() {
+        let samples = tree.sample_nodes();
+        let ancestors = samples
+            .iter()
+            .map(|s| {
+                let mut a = vec![*s];
+                a.extend(tree.parents(*s).unwrap());
+                a
+            })
+            .collect::<Vec<_>>();
+        let mut tmrca = vec![];
+        for i in 0..samples.len() {
+            for j in i + 1..samples.len() {
+                let t = ancestors[i]
+                    .iter()
+                    .find(|a| ancestors[j].contains(a))
+                    .map_or(f64::INFINITY, |a| f64::from(nodes.time(*a).unwrap()));
+                tmrca.push(t);
+            }
+        }
+        let (left, right) = tree.interval();
+        rv.push(((f64::from(left), f64::from(right)), tmrca));
+    }
+    rv
+}
+
+#[test]
+fn test_edge_buffer_matches_full_sort() {
+    for pdeath in [0.5, 1.0] {
+        for simplification_interval in [1, 5, 100] {
+            let p = Parameters::new(pdeath, 1e-1, 100).unwrap();
+
+            let mut t = EvolvableTableCollection::new(100, 10, simplification_interval).unwrap();
+            evolve(101, p, &mut t).unwrap();
+
+            let mut buffered =
+                EvolvableTableCollection::new(100, 10, simplification_interval).unwrap();
+            buffered.set_sorting_mode(SortingMode::EdgeBuffer);
+            evolve(101, p, &mut buffered).unwrap();
+
+            let ts = tskit::TreeSequence::try_from(t).unwrap();
+            let buffered_ts = tskit::TreeSequence::try_from(buffered)
+                .unwrap_or_else(|e| panic!("{} {} {}", e, pdeath, simplification_interval));
+            assert_eq!(ts.nodes().num_rows(), buffered_ts.nodes().num_rows());
+            assert_eq!(ts.edges().num_rows(), buffered_ts.edges().num_rows());
+            assert_eq!(pairwise_tmrca(&ts), pairwise_tmrca(&buffered_ts));
+        }
+    }
+}
+
+#[test]
+fn test_simplify_every_step_matches_once() {
+    for pdeath in [0.5, 1.0] {
+        let p = Parameters::new(pdeath, 1e-1, 100).unwrap();
+
+        let mut every_step = EvolvableTableCollection::new(100, 10, 1).unwrap();
+        evolve(101, p, &mut every_step).unwrap();
+
+        let mut once = EvolvableTableCollection::new(100, 10, 100).unwrap();
+        evolve(101, p, &mut once).unwrap();
+
+        let every_step_ts = tskit::TreeSequence::try_from(every_step).unwrap();
+        let once_ts = tskit::TreeSequence::try_from(once).unwrap();
+        assert_eq!(every_step_ts.nodes().num_rows(), once_ts.nodes().num_rows());
+        assert_eq!(every_step_ts.edges().num_rows(), once_ts.edges().num_rows());
+        assert_eq!(pairwise_tmrca(&every_step_ts), pairwise_tmrca(&once_ts));
+    }
+}
+
+#[test]
+fn test_timed_matches_untimed() {
+    let p = Parameters::new(0.5, 1e-1, 100).unwrap();
+
+    let mut t = EvolvableTableCollection::new(100, 10, 10).unwrap();
+    evolve(101, p, &mut t).unwrap();
+
+    let mut timed = Timed::new(EvolvableTableCollection::new(100, 10, 10).unwrap());
+    evolve(101, p, &mut timed).unwrap();
+    let report = timed.report();
+    assert!(report.generate_deaths > std::time::Duration::ZERO);
+    assert!(report.record_birth > std::time::Duration::ZERO);
+    assert!(report.simplify > std::time::Duration::ZERO);
+
+    let tables = tskit::TableCollection::from(t);
+    let timed_tables = tskit::TableCollection::from(timed.into_inner());
+    assert!(tables.equals(&timed_tables, tskit::TableEqualityOptions::default()));
+}
+
+#[test]
+fn test_coalescence_fraction_history() {
+    let mut t = EvolvableTableCollection::new(100, 10, 1).unwrap();
+    t.track_coalescence_fraction(true);
+    let p = Parameters::new(1.0, 1e-1, 200).unwrap();
+    evolve(101, p, &mut t).unwrap();
+    let history = t.coalescence_fraction_history();
+    assert_eq!(history.len(), 200);
+    assert_eq!(history[0].0, 1);
+    assert!(history[0].1 < 1.0);
+    // Once coalesced, a position stays coalesced
+    assert!(history.windows(2).all(|w| w[0].1 <= w[1].1));
+    assert_eq!(history.last().unwrap().1, 1.0);
+}